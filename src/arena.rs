@@ -0,0 +1,83 @@
+//! Statistics about the chunks of a bump arena.
+
+use crate::tracking::TrackingStats;
+use alloc_wg::alloc::AllocRef;
+use bumpalo::Bump;
+use std::fmt;
+
+/// The chunks that a bump arena holds after running a scenario, and how many it requested.
+pub struct ChunkStats {
+    /// The number of chunks that the arena holds.
+    pub chunks: usize,
+    /// The number of bytes in those chunks.
+    pub chunk_bytes: usize,
+    /// The number of bytes of those chunks that were handed out, including alignment padding.
+    pub used_bytes: usize,
+    /// The number of chunks that the arena requested in the first round.
+    pub first_round_requests: u64,
+    /// The average number of chunks that the arena requested in later rounds, after a reset.
+    pub later_round_requests: Option<f64>,
+}
+
+impl ChunkStats {
+    /// Collects the chunks of `bump`. `parent` tracks the allocator that backs it, which
+    /// allocated `first_round_requests` chunks during the first of `rounds` rounds.
+    pub fn new<A: AllocRef>(
+        bump: &mut Bump<A>,
+        parent: &TrackingStats,
+        first_round_requests: u64,
+        rounds: usize,
+    ) -> Self {
+        let mut chunks = 0;
+        let mut used_bytes = 0;
+        // No allocation of the arena is referenced anymore.
+        unsafe {
+            bump.each_allocated_chunk(|chunk| {
+                chunks += 1;
+                used_bytes += chunk.len();
+            })
+        };
+
+        let later_round_requests = if rounds > 1 {
+            let later = parent.allocs - first_round_requests;
+            Some(later as f64 / (rounds - 1) as f64)
+        } else {
+            None
+        };
+        ChunkStats {
+            chunks,
+            chunk_bytes: parent.live_bytes,
+            used_bytes,
+            first_round_requests,
+            later_round_requests,
+        }
+    }
+
+    /// The number of bytes in chunks that were never handed out, including the bookkeeping of
+    /// the arena.
+    pub fn unused_bytes(&self) -> usize {
+        self.chunk_bytes.saturating_sub(self.used_bytes)
+    }
+}
+
+impl fmt::Display for ChunkStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "chunks:   {} held; ", self.chunks)?;
+        match self.later_round_requests {
+            Some(later) => write!(
+                f,
+                "{} requested in the first round, {:.2} per round after a reset",
+                self.first_round_requests, later
+            )?,
+            None => write!(f, "{} requested", self.first_round_requests)?,
+        }
+        let unused = self.unused_bytes();
+        write!(
+            f,
+            "\nwaste:    {} of {} chunk bytes unused ({:.1}%)",
+            unused,
+            self.chunk_bytes,
+            unused as f64 / self.chunk_bytes.max(1) as f64 * 100.0
+        )
+    }
+}
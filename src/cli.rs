@@ -0,0 +1,634 @@
+use crate::clock::Clock;
+use crate::counters::Counters;
+use crate::distribution::{self, AlignDistribution, SizeDistribution};
+use crate::failing::Failure;
+use crate::matrix::Isolation;
+use crate::scenario::{Allocator, Call, Choice, Mode, Pattern, Scenario, Sizes};
+use crate::stats::MeasureConfig;
+use rand::{thread_rng, Rng};
+use std::fs;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Benchmarks the call-by-value AllocRefV2 API against different allocators.
+
+USAGE:
+    bench-alloc <COMMAND> [OPTIONS]
+    bench-alloc replay <TRACE> [OPTIONS]
+    bench-alloc export <TRACE> [OPTIONS]
+
+COMMANDS:
+    run       Measure a single scenario
+    matrix    Measure every combination of allocator, size distribution, call style and mode
+    replay    Measure replaying an allocation trace, e.g. one captured with `bench_alloc::recorder`;
+              only --allocator, --clock, --samples, --warmup, --resamples and --confidence
+              apply
+    track     Run a scenario once through a wrapper that counts the calls, bytes and layouts
+              that reach the allocator; for bump, also the chunks that it requests
+    export    Write the layouts of a scenario as an allocation trace, e.g. to test `replay`
+    list      List the available allocators, size distributions, call styles and modes
+    help      Print this message
+
+OPTIONS:
+    --iters <N>            Number of operations per sample [default: 1000000]
+    --seed <N>             Seed for generating layouts [default: random, printed to stderr]
+    --allocator <NAME>     Allocator to measure [default: global]
+    --sizes <NAME>         Whether allocations are zero-sized [default: nonzero]
+    --size-dist <DIST>     Distribution of non-zero sizes [default: uniform:1..1024]
+    --align-dist <DIST>    Distribution of alignments [default: pow2:1..8]
+    --zst-ratio <R>        Fraction of zero-sized layouts with mixed sizes [default: 0.5]
+    --pattern <NAME>       Interleaving of zero-sized layouts with mixed sizes [default: random]
+    --burst <N>            Mean run length of the rarer kind of layout for `bursty` [default: 32]
+    --fail <SPEC>          Fail allocating calls on purpose: every:N, after-bytes:N or random:P,
+                           seeded by --seed; without it, a call that fails is an error
+                           [default: none]
+    --call <NAME>          Style of AllocRefV2 calls [default: branched]
+    --mode <NAME>          Operations to time [default: alloc]
+    --samples <N>          Number of timed samples [default: 30]
+    --warmup <N>           Number of untimed warmup runs [default: 3]
+    --resamples <N>        Number of bootstrap resamples of the confidence interval [default: 10000]
+    --confidence <P>       Confidence level of the interval, between 0 and 1 [default: 0.95]
+    --latency-batch <N>    Also report latency percentiles over batches of N operations
+    --clock <NAME>         Clock that timings are read from [default: instant]
+    --counters <NAME>      Event counters to report per allocation with `run` [default: none]
+    --format <NAME>        Output format [default: text]
+    --analysis <NAME>      Untimed analysis to print next to the timings of `run` [default: none]
+    --isolation <NAME>     Isolation between the scenarios of `matrix` [default: process]
+    --config <FILE>        Read options from FILE, with one `name = value` pair per line, e.g.
+                           `size-dist = log-uniform:1..65536`; later options override it
+
+For `matrix`, the allocator, sizes, pattern, call and mode options restrict the combinations
+that are run, and the results are printed as a single table.
+
+With mixed sizes, direct calls run the zero-sized and non-zero-sized layouts as separate loops,
+as if an oracle dispatched every layout without branching. Comparing them with branched calls
+shows what mispredicting the `layout.size() == 0` branch costs.
+
+EXAMPLE:
+    cargo run --release -- run --iters 10000000 --allocator bump --sizes zero --call direct";
+
+/// The maximum depth of nested `--config` files.
+const MAX_CONFIG_DEPTH: usize = 8;
+
+/// Options shared by the `run` and `matrix` commands.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub iters: usize,
+    pub seed: u64,
+    pub allocator: Option<Allocator>,
+    pub sizes: Option<Sizes>,
+    pub call: Option<Call>,
+    pub mode: Option<Mode>,
+    pub size_dist: SizeDistribution,
+    pub align_dist: AlignDistribution,
+    pub zst_ratio: f64,
+    pub pattern: Option<Pattern>,
+    pub burst: usize,
+    pub failure: Option<Failure>,
+    pub config: MeasureConfig,
+    /// Number of operations per latency batch, or zero to disable latency reporting.
+    pub latency_batch: usize,
+    pub clock: Clock,
+    pub counters: Counters,
+    pub format: Format,
+    pub analysis: Analysis,
+    pub isolation: Isolation,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            iters: 1_000_000,
+            seed: 0,
+            allocator: None,
+            sizes: None,
+            call: None,
+            mode: None,
+            size_dist: SizeDistribution::default(),
+            align_dist: AlignDistribution::default(),
+            zst_ratio: 0.5,
+            pattern: None,
+            burst: 32,
+            failure: None,
+            config: MeasureConfig::default(),
+            latency_batch: 0,
+            clock: Clock::Instant,
+            counters: Counters::None,
+            format: Format::Text,
+            analysis: Analysis::None,
+            isolation: Isolation::Process,
+        }
+    }
+}
+
+impl Options {
+    /// The scenario selected by the `run` command, with defaults for unspecified options.
+    pub fn scenario(&self) -> Scenario {
+        Scenario {
+            iters: self.iters,
+            seed: self.seed,
+            size_dist: self.size_dist.clone(),
+            align_dist: self.align_dist.clone(),
+            zst_ratio: self.zst_ratio,
+            pattern: self.pattern.unwrap_or(Pattern::Random),
+            burst: self.burst,
+            allocator: self.allocator.unwrap_or(Allocator::Global),
+            sizes: self.sizes.unwrap_or(Sizes::NonZero),
+            call: self.call.unwrap_or(Call::Branched),
+            mode: self.mode.unwrap_or(Mode::Alloc),
+            failure: self.failure.clone(),
+        }
+    }
+
+    /// All valid scenarios that match the options of the `matrix` command.
+    pub fn scenarios(&self) -> Vec<Scenario> {
+        let mut scenarios = Vec::new();
+        for &allocator in filter(self.allocator) {
+            for &sizes in filter(self.sizes) {
+                // The pattern only matters when zero-sized and non-zero-sized layouts are mixed.
+                let patterns = if sizes == Sizes::Mixed {
+                    filter(self.pattern)
+                } else {
+                    filter(Some(self.pattern.unwrap_or(Pattern::Random)))
+                };
+                for &pattern in patterns {
+                    for &call in filter(self.call) {
+                        for &mode in filter(self.mode) {
+                            let scenario = Scenario {
+                                iters: self.iters,
+                                seed: self.seed,
+                                size_dist: self.size_dist.clone(),
+                                align_dist: self.align_dist.clone(),
+                                zst_ratio: self.zst_ratio,
+                                pattern,
+                                burst: self.burst,
+                                allocator,
+                                sizes,
+                                call,
+                                mode,
+                                failure: self.failure.clone(),
+                            };
+                            if scenario.validate().is_ok() {
+                                scenarios.push(scenario);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        scenarios
+    }
+}
+
+fn filter<T: Choice + PartialEq>(selected: Option<T>) -> &'static [T] {
+    match selected {
+        Some(choice) => {
+            let index = T::ALL.iter().position(|c| *c == choice).unwrap();
+            &T::ALL[index..=index]
+        }
+        None => T::ALL,
+    }
+}
+
+/// How results are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Record,
+}
+
+impl Choice for Format {
+    const WHAT: &'static str = "format";
+    const ALL: &'static [Self] = &[Format::Text, Format::Json, Format::Csv, Format::Record];
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Record => "record",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Format::Text => "human-readable statistics",
+            Format::Json => "one JSON object per scenario, with environment metadata",
+            Format::Csv => "a header and one row per scenario, with environment metadata",
+            Format::Record => "a single-line summary, used by `matrix` to collect results",
+        }
+    }
+}
+
+/// An untimed analysis that is printed next to the timings of `run`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Analysis {
+    None,
+    Padding,
+    Verify,
+}
+
+impl Choice for Analysis {
+    const WHAT: &'static str = "analysis";
+    const ALL: &'static [Self] = &[Analysis::None, Analysis::Padding, Analysis::Verify];
+
+    fn name(self) -> &'static str {
+        match self {
+            Analysis::None => "none",
+            Analysis::Padding => "padding",
+            Analysis::Verify => "verify",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Analysis::None => "only report timings",
+            Analysis::Padding => "alignment padding, footprint and fragmentation of all layouts",
+            Analysis::Verify => {
+                "check every pointer that the allocator returns; exit 1 if one is invalid"
+            }
+        }
+    }
+}
+
+pub enum Command {
+    Run(Options),
+    Matrix(Options),
+    Replay(String, Options),
+    Export(String, Options),
+    Track(Options),
+    List,
+    Help,
+}
+
+/// Parses the command-line arguments, excluding the program name.
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let args: Vec<String> = args.collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help);
+    }
+
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Command::Help),
+    };
+
+    match command.as_str() {
+        "run" => {
+            let options = parse_options(args, true)?;
+            options.scenario().validate()?;
+            if options.analysis != Analysis::None && options.format != Format::Text {
+                return Err("Analyses are only printed in the text format.".to_owned());
+            }
+            Ok(Command::Run(options))
+        }
+        "matrix" => {
+            let options = parse_options(args, true)?;
+            if options.scenarios().is_empty() {
+                return Err("The selected options do not match any valid scenario.".to_owned());
+            }
+            if options.latency_batch > 0 {
+                return Err("Latency percentiles are only reported by `run`.".to_owned());
+            }
+            if options.analysis != Analysis::None {
+                return Err("Analyses are only reported by `run`.".to_owned());
+            }
+            check_counters(&options)?;
+            Ok(Command::Matrix(options))
+        }
+        "replay" => {
+            let path = parse_path(&mut args)?;
+            let options = parse_options(args, false)?;
+            if options.format != Format::Text {
+                return Err("`replay` only supports the text format.".to_owned());
+            }
+            check_counters(&options)?;
+            Ok(Command::Replay(path, options))
+        }
+        "export" => {
+            let path = parse_path(&mut args)?;
+            let options = parse_options(args, true)?;
+            options.scenario().validate()?;
+            check_counters(&options)?;
+            Ok(Command::Export(path, options))
+        }
+        "track" => {
+            let options = parse_options(args, true)?;
+            options.scenario().validate()?;
+            if options.format != Format::Text {
+                return Err("`track` only supports the text format.".to_owned());
+            }
+            check_counters(&options)?;
+            Ok(Command::Track(options))
+        }
+        "list" => Ok(Command::List),
+        "help" => Ok(Command::Help),
+        _ => Err(format!(
+            "Unknown command '{}'; expected 'run', 'matrix', 'replay', 'export', 'track', 'list' or 'help'.",
+            command
+        )),
+    }
+}
+
+/// Event counters are only selected for `run`, so other commands reject them instead of
+/// silently ignoring them.
+fn check_counters(options: &Options) -> Result<(), String> {
+    if options.counters != Counters::None {
+        return Err("Event counters are only reported by `run`.".to_owned());
+    }
+    Ok(())
+}
+
+fn parse_path<I: Iterator<Item = String>>(args: &mut I) -> Result<String, String> {
+    match args.next() {
+        Some(path) if !path.starts_with("--") => Ok(path),
+        _ => Err("Missing the path of the trace.".to_owned()),
+    }
+}
+
+/// Parses the options of a command. Unless `uses_seed` is false, a random seed is chosen when
+/// none is given.
+fn parse_options<I: Iterator<Item = String>>(
+    mut args: I,
+    uses_seed: bool,
+) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut seed = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Err(format!("Unexpected argument '{}'.", arg));
+        }
+
+        // Both `--flag value` and `--flag=value` are accepted.
+        let (flag, value) = match arg.find('=') {
+            Some(index) => (arg[..index].to_owned(), arg[index + 1..].to_owned()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for '{}'.", arg))?;
+                (arg, value)
+            }
+        };
+
+        apply_option(&mut options, &mut seed, &flag, &value, 0)?;
+    }
+
+    options.seed = match seed {
+        Some(seed) => seed,
+        None if !uses_seed => 0,
+        None => {
+            let seed = thread_rng().gen();
+            eprintln!(
+                "Using random seed {}; pass `--seed {}` to replay this run.",
+                seed, seed
+            );
+            seed
+        }
+    };
+    options.config.seed = options.seed;
+
+    Ok(options)
+}
+
+/// Applies a single option. `depth` is the number of config files that the option is nested in.
+fn apply_option(
+    options: &mut Options,
+    seed: &mut Option<u64>,
+    flag: &str,
+    value: &str,
+    depth: usize,
+) -> Result<(), String> {
+    match flag {
+        "--iters" => options.iters = parse_number(flag, value)?,
+        "--seed" => *seed = Some(parse_number(flag, value)?),
+        "--allocator" => options.allocator = Some(Allocator::parse(value)?),
+        "--sizes" => options.sizes = Some(Sizes::parse(value)?),
+        "--fail" => {
+            options.failure = match value {
+                "none" => None,
+                _ => Some(
+                    value
+                        .parse()
+                        .map_err(|error| format!("Invalid value for '{}': {}", flag, error))?,
+                ),
+            }
+        }
+        "--call" => options.call = Some(Call::parse(value)?),
+        "--mode" => options.mode = Some(Mode::parse(value)?),
+        "--size-dist" => {
+            options.size_dist = value
+                .parse()
+                .map_err(|error| format!("Invalid value for '{}': {}", flag, error))?
+        }
+        "--align-dist" => {
+            options.align_dist = value
+                .parse()
+                .map_err(|error| format!("Invalid value for '{}': {}", flag, error))?
+        }
+        "--zst-ratio" => {
+            options.zst_ratio = value.parse().map_err(|_| {
+                format!(
+                    "Invalid value '{}' for '{}'; expected a number.",
+                    value, flag
+                )
+            })?
+        }
+        "--pattern" => options.pattern = Some(Pattern::parse(value)?),
+        "--burst" => options.burst = parse_number(flag, value)?,
+        "--samples" => {
+            options.config.samples = parse_number(flag, value)?;
+            if options.config.samples == 0 {
+                return Err("The number of samples must be greater than zero.".to_owned());
+            }
+        }
+        "--warmup" => options.config.warmup = parse_number(flag, value)?,
+        "--resamples" => options.config.resamples = parse_number(flag, value)?,
+        "--confidence" => {
+            options.config.confidence = match value.parse() {
+                Ok(confidence) if confidence > 0.0 && confidence < 1.0 => confidence,
+                _ => {
+                    return Err(format!(
+                        "Invalid value '{}' for '{}'; expected a number between 0 and 1.",
+                        value, flag
+                    ))
+                }
+            }
+        }
+        "--latency-batch" => options.latency_batch = parse_number(flag, value)?,
+        "--clock" => options.clock = Clock::parse(value)?,
+        "--counters" => options.counters = Counters::parse(value)?,
+        "--format" => options.format = Format::parse(value)?,
+        "--analysis" => options.analysis = Analysis::parse(value)?,
+        "--isolation" => options.isolation = Isolation::parse(value)?,
+        "--config" => apply_config(options, seed, value, depth)?,
+        _ => return Err(format!("Unknown option '{}'.", flag)),
+    }
+    Ok(())
+}
+
+/// Applies the options of a config file, which contains one `name = value` pair per line.
+/// Empty lines and lines starting with `#` are ignored.
+fn apply_config(
+    options: &mut Options,
+    seed: &mut Option<u64>,
+    path: &str,
+    depth: usize,
+) -> Result<(), String> {
+    if depth >= MAX_CONFIG_DEPTH {
+        return Err(format!("Config file '{}' is nested too deeply.", path));
+    }
+
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Cannot read config file '{}': {}", path, error))?;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let location = format!("{}:{}", path, index + 1);
+        let equals = line
+            .find('=')
+            .ok_or_else(|| format!("{}: expected `name = value`.", location))?;
+        let name = line[..equals].trim();
+        let value = line[equals + 1..].trim().trim_matches('"');
+
+        apply_option(options, seed, &format!("--{}", name), value, depth + 1)
+            .map_err(|error| format!("{}: {}", location, error))?;
+    }
+    Ok(())
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "Invalid value '{}' for '{}'; expected a non-negative integer.",
+            value, flag
+        )
+    })
+}
+
+/// Prints every option of every choice, for the `list` command.
+pub fn list() {
+    print_choices::<Allocator>("Allocators (--allocator)");
+    print_choices::<Sizes>("Sizes (--sizes)");
+    print_choices::<Pattern>("Patterns of mixed sizes (--pattern)");
+    print_choices::<Call>("Call styles (--call)");
+    print_choices::<Mode>("Modes (--mode)");
+    print_choices::<Clock>("Clocks (--clock)");
+    print_choices::<Counters>("Event counters (--counters)");
+    print_choices::<Format>("Output formats (--format)");
+    print_choices::<Analysis>("Analyses (--analysis)");
+    print_choices::<Isolation>("Matrix isolation (--isolation)");
+    println!("{}", distribution::SYNTAX);
+}
+
+fn print_choices<T: Choice>(title: &str) {
+    println!("{}:", title);
+    for choice in T::ALL {
+        println!("    {:<16}{}", choice.name(), choice.description());
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Command, Options, MAX_CONFIG_DEPTH};
+    use crate::counters::Counters;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn parse_args(args: &str) -> Result<Command, String> {
+        parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    fn run_options(args: &str) -> Options {
+        match parse_args(args) {
+            Ok(Command::Run(options)) => options,
+            Ok(_) => panic!("'{}' is not parsed as `run`", args),
+            Err(error) => panic!("'{}' is rejected: {}", args, error),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_args(args) {
+            Ok(_) => panic!("'{}' is accepted", args),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn values_follow_the_flag_or_an_equals_sign() {
+        let separate = run_options("run --seed 7 --iters 100 --samples 5 --confidence 0.9");
+        let joined = run_options("run --seed=7 --iters=100 --samples=5 --confidence=0.9");
+        for options in &[separate, joined] {
+            assert_eq!(options.seed, 7);
+            assert_eq!(options.config.seed, 7);
+            assert_eq!(options.iters, 100);
+            assert_eq!(options.config.samples, 5);
+            assert_eq!(options.config.confidence, 0.9);
+        }
+    }
+
+    #[test]
+    fn missing_values_and_unknown_options_are_rejected() {
+        assert_eq!(
+            error("run --seed 1 --iters"),
+            "Missing value for '--iters'."
+        );
+        assert_eq!(
+            error("run --seed 1 --frobnicate 3"),
+            "Unknown option '--frobnicate'."
+        );
+        assert_eq!(error("run --seed 1 iters"), "Unexpected argument 'iters'.");
+    }
+
+    #[test]
+    fn measurement_options_are_validated() {
+        assert!(error("run --seed 1 --samples 0").contains("greater than zero"));
+        for confidence in &["0", "1", "-0.5", "1.5", "NaN", "high"] {
+            let message = error(&format!("run --seed 1 --confidence {}", confidence));
+            assert!(message.contains("between 0 and 1"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn counters_are_only_accepted_by_run() {
+        assert_eq!(
+            run_options("run --seed 1 --counters software").counters,
+            Counters::Software
+        );
+        for command in &["matrix", "replay trace", "export trace", "track"] {
+            let message = error(&format!("{} --seed 1 --counters software", command));
+            assert!(message.contains("only reported by `run`"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn config_files_are_applied_and_may_not_nest_too_deeply() {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("bench-alloc-{}.conf", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+
+        fs::write(&path, "# Comment\n\niters = 100\nsamples = \"5\"\n").unwrap();
+        let options = run_options(&format!("run --seed 1 --config {}", path));
+        assert_eq!(options.iters, 100);
+        assert_eq!(options.config.samples, 5);
+
+        // A config file that includes itself is read `MAX_CONFIG_DEPTH` times, and every nested
+        // read prefixes the error with its location.
+        fs::write(&path, format!("config = {}\n", path)).unwrap();
+        let message = error(&format!("run --seed 1 --config {}", path));
+        fs::remove_file(&path).unwrap();
+        assert!(message.contains("nested too deeply"), "{}", message);
+        assert_eq!(
+            message.matches(&format!("{}:1: ", path)).count(),
+            MAX_CONFIG_DEPTH
+        );
+    }
+}
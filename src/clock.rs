@@ -0,0 +1,285 @@
+//! The clocks that timings can be read from. A clock is selected once for the whole process,
+//! and every timed region reads it through `now`. The region also starts and stops the event
+//! counters, outside of the readings.
+
+use crate::counters;
+use crate::scenario::Choice;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long the TSC is compared against `Instant` to calibrate its frequency.
+const CALIBRATION: Duration = Duration::from_millis(50);
+
+/// The selected clock, as an index into `Clock::ALL`.
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// The calibrated frequency of the TSC in ticks per second, as the bits of an `f64`.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clock {
+    Instant,
+    MonotonicRaw,
+    ThreadCpuTime,
+    Rdtsc,
+    Rdtscp,
+}
+
+impl Choice for Clock {
+    const WHAT: &'static str = "clock";
+    const ALL: &'static [Self] = &[
+        Clock::Instant,
+        Clock::MonotonicRaw,
+        Clock::ThreadCpuTime,
+        Clock::Rdtsc,
+        Clock::Rdtscp,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Clock::Instant => "instant",
+            Clock::MonotonicRaw => "monotonic-raw",
+            Clock::ThreadCpuTime => "thread-cputime",
+            Clock::Rdtsc => "rdtsc",
+            Clock::Rdtscp => "rdtscp",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Clock::Instant => "std::time::Instant, the monotonic clock of the platform",
+            Clock::MonotonicRaw => "CLOCK_MONOTONIC_RAW, not slewed by NTP (Linux)",
+            Clock::ThreadCpuTime => {
+                "CLOCK_THREAD_CPUTIME_ID, the CPU time of this thread only (Linux)"
+            }
+            Clock::Rdtsc => "the time-stamp counter, calibrated against Instant (x86_64)",
+            Clock::Rdtscp => "like rdtsc, but waits for earlier instructions to finish (x86_64)",
+        }
+    }
+}
+
+impl Clock {
+    fn is_tsc(self) -> bool {
+        self == Clock::Rdtsc || self == Clock::Rdtscp
+    }
+
+    /// Whether the clock can be read on this platform.
+    fn check(self) -> Result<(), String> {
+        let available = match self {
+            Clock::Instant => true,
+            Clock::MonotonicRaw | Clock::ThreadCpuTime => cfg!(target_os = "linux"),
+            Clock::Rdtsc | Clock::Rdtscp => cfg!(target_arch = "x86_64"),
+        };
+        if available {
+            Ok(())
+        } else {
+            Err(format!(
+                "The clock '{}' is not available on this platform.",
+                self.name()
+            ))
+        }
+    }
+
+    /// Reads the clock, in nanoseconds or TSC ticks. `Instant` has no raw reading.
+    #[inline(always)]
+    fn read(self) -> u64 {
+        match self {
+            Clock::Instant => unreachable!("Instant is read through Timestamp::Instant"),
+            Clock::MonotonicRaw => posix::read(posix::CLOCK_MONOTONIC_RAW),
+            Clock::ThreadCpuTime => posix::read(posix::CLOCK_THREAD_CPUTIME_ID),
+            Clock::Rdtsc => tsc::rdtsc(),
+            Clock::Rdtscp => tsc::rdtscp(),
+        }
+    }
+
+    /// Converts the difference between two readings into a duration.
+    fn to_duration(self, ticks: u64) -> Duration {
+        if self.is_tsc() {
+            Duration::from_nanos((ticks as f64 * 1e9 / tsc_frequency()) as u64)
+        } else {
+            Duration::from_nanos(ticks)
+        }
+    }
+}
+
+/// Selects the clock that `now` reads from, for the rest of the process. The frequency of the
+/// TSC is calibrated first.
+pub fn select(clock: Clock) -> Result<(), String> {
+    clock.check()?;
+    if clock.is_tsc() {
+        TSC_FREQUENCY.store(calibrate(clock).to_bits(), Ordering::Relaxed);
+        if !tsc::is_invariant() {
+            eprintln!(
+                "warning: the TSC is not invariant; its rate may change with the power state"
+            );
+        }
+    }
+    let index = Clock::ALL.iter().position(|c| *c == clock).unwrap();
+    SELECTED.store(index, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn selected() -> Clock {
+    Clock::ALL[SELECTED.load(Ordering::Relaxed)]
+}
+
+/// The selected clock, with the calibrated frequency of the TSC.
+pub fn describe() -> String {
+    let clock = selected();
+    if clock.is_tsc() {
+        format!("{} at {:.3} GHz", clock.name(), tsc_frequency() / 1e9)
+    } else {
+        clock.name().to_owned()
+    }
+}
+
+fn tsc_frequency() -> f64 {
+    f64::from_bits(TSC_FREQUENCY.load(Ordering::Relaxed))
+}
+
+/// Counts the ticks of `clock` while `Instant` advances by `CALIBRATION`, and returns the
+/// number of ticks per second.
+fn calibrate(clock: Clock) -> f64 {
+    let (before, start) = (Instant::now(), clock.read());
+    while before.elapsed() < CALIBRATION {}
+    let (elapsed, ticks) = (before.elapsed(), clock.read() - start);
+    ticks as f64 * 1e9 / elapsed.as_nanos() as f64
+}
+
+/// A reading of the selected clock.
+#[derive(Clone, Copy)]
+pub enum Timestamp {
+    Instant(Instant),
+    Ticks(Clock, u64),
+}
+
+/// Reads the selected clock.
+#[inline(always)]
+pub fn now() -> Timestamp {
+    counters::start();
+    match selected() {
+        Clock::Instant => Timestamp::Instant(Instant::now()),
+        clock => Timestamp::Ticks(clock, clock.read()),
+    }
+}
+
+impl Timestamp {
+    /// The time that passed since the reading, on the same clock.
+    #[inline(always)]
+    pub fn elapsed(self) -> Duration {
+        let elapsed = match self {
+            Timestamp::Instant(instant) => instant.elapsed(),
+            Timestamp::Ticks(clock, start) => clock.to_duration(clock.read().wrapping_sub(start)),
+        };
+        counters::stop();
+        elapsed
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod posix {
+    use std::os::raw::{c_int, c_long};
+
+    pub const CLOCK_THREAD_CPUTIME_ID: c_int = 3;
+    pub const CLOCK_MONOTONIC_RAW: c_int = 4;
+
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn clock_gettime(clock: c_int, tp: *mut Timespec) -> c_int;
+    }
+
+    /// Reads `clock` in nanoseconds.
+    #[inline(always)]
+    pub fn read(clock: c_int) -> u64 {
+        let mut time = Timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let result = unsafe { clock_gettime(clock, &mut time) };
+        debug_assert_eq!(result, 0);
+        time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod posix {
+    pub const CLOCK_THREAD_CPUTIME_ID: i32 = 0;
+    pub const CLOCK_MONOTONIC_RAW: i32 = 0;
+
+    pub fn read(_clock: i32) -> u64 {
+        unreachable!("Clock::check rejects the POSIX clocks on this platform")
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod tsc {
+    use std::arch::x86_64::{__cpuid, __rdtscp, _rdtsc};
+
+    #[inline(always)]
+    pub fn rdtsc() -> u64 {
+        unsafe { _rdtsc() }
+    }
+
+    #[inline(always)]
+    pub fn rdtscp() -> u64 {
+        let mut processor = 0;
+        unsafe { __rdtscp(&mut processor) }
+    }
+
+    /// Whether the TSC ticks at a constant rate in every power state, according to CPUID.
+    // `__cpuid` became safe to call in later Rust versions.
+    #[allow(unused_unsafe)]
+    pub fn is_invariant() -> bool {
+        let max_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+        max_extended >= 0x8000_0007 && unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8) != 0
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod tsc {
+    pub fn rdtsc() -> u64 {
+        unreachable!("Clock::check rejects the TSC on this platform")
+    }
+
+    pub fn rdtscp() -> u64 {
+        rdtsc()
+    }
+
+    pub fn is_invariant() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calibrate, Clock, TSC_FREQUENCY};
+    use crate::scenario::Choice;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn every_available_clock_measures_a_busy_loop() {
+        for &clock in Clock::ALL.iter().filter(|clock| clock.check().is_ok()) {
+            if clock == Clock::Instant {
+                continue;
+            }
+            if clock.is_tsc() {
+                TSC_FREQUENCY.store(calibrate(clock).to_bits(), Ordering::Relaxed);
+            }
+            let (before, start) = (Instant::now(), clock.read());
+            while before.elapsed() < Duration::from_millis(20) {}
+            let measured = clock.to_duration(clock.read() - start);
+            assert!(
+                measured > Duration::from_millis(5) && measured < Duration::from_secs(1),
+                "{} measured {:?}",
+                clock.name(),
+                measured
+            );
+        }
+    }
+}
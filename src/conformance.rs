@@ -0,0 +1,444 @@
+//! A test suite that any `AllocRefV2` implementation can be run through.
+
+use crate::cli::Options;
+use crate::free_list::FreeList;
+use crate::linear::Linear;
+use crate::malloc::{AlignedAlloc, Malloc, PosixMemalign};
+use crate::pool::Pool;
+use crate::property::{self, Config, Shrink};
+use crate::scenario::{Allocator, Choice, Mode, Scenario, Sizes};
+use crate::slab::Slab;
+use crate::verify::Verifying;
+use crate::{free_list_for, non_zero, track, AllocRefV2, Workload};
+use alloc_wg::alloc::{Global, NonZeroLayout};
+use bumpalo::Bump;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::{Layout, System};
+use std::convert::TryFrom;
+use std::mem::{self, MaybeUninit};
+use std::ptr::NonNull;
+
+/// The largest alignment that the suite grows zero-sized allocations to. Larger ones are only
+/// allocated as zero-sized.
+const MAX_GROWN_ALIGN: usize = 4096;
+
+/// The number of operations in a random sequence at most.
+const MAX_OPERATIONS: usize = 64;
+
+/// The capacity of the allocators that manage a fixed amount of memory, which fits any random
+/// sequence.
+const CAPACITY: usize = 1 << 20;
+
+/// Creates a layout that is known to be valid, for the tests of every allocator.
+pub fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// Every power-of-two alignment that a zero-sized layout can have.
+fn zst_layouts() -> impl Iterator<Item = Layout> {
+    (0..mem::size_of::<usize>() * 8).filter_map(|shift| Layout::from_size_align(0, 1 << shift).ok())
+}
+
+/// Zero-sized allocations get the dangling pointer for their alignment, without failing, and
+/// can be grown into real allocations.
+fn zsts_for_every_alignment<A: AllocRefV2>(a: A) {
+    for zst in zst_layouts() {
+        let dangling = zst.align() as *mut u8;
+        for ptr in &[a.alloc(zst), a.alloc_zeroed(zst), a.alloc_zst(zst)] {
+            assert_eq!(ptr.unwrap().as_ptr(), dangling, "{:?}", zst);
+        }
+        unsafe { a.dealloc(NonNull::new_unchecked(dangling), zst) };
+
+        if zst.align() <= MAX_GROWN_ALIGN {
+            let ptr = NonNull::new(dangling).unwrap();
+            if let Ok(grown) = unsafe { a.grow(ptr, zst, 1) } {
+                assert_eq!(grown.as_ptr() as usize & (zst.align() - 1), 0, "{:?}", zst);
+                let shrunk = unsafe { a.shrink(grown, layout(1, zst.align()), 0) }.unwrap();
+                assert_eq!(shrunk.as_ptr(), dangling, "{:?}", zst);
+            }
+        }
+    }
+}
+
+/// Sizes near `isize::MAX` fail to allocate or to grow to, instead of panicking or overflowing,
+/// and the allocator stays usable afterwards.
+fn large_sizes<A: AllocRefV2>(a: A) {
+    let max = isize::MAX as usize;
+    for &(size, align) in &[(max, 1), (max - 1, 1), (max / 2 + 1, 1), (max - 15, 16)] {
+        let large = layout(size, align);
+        for ptr in vec![a.alloc(large), a.alloc_zeroed(large)]
+            .into_iter()
+            .flatten()
+        {
+            assert_eq!(ptr.as_ptr() as usize & (align - 1), 0, "{:?}", large);
+            unsafe { a.dealloc(ptr, large) };
+        }
+
+        let small = layout(64, align);
+        let ptr = a.alloc(small).unwrap();
+        unsafe {
+            ptr.as_ptr().write_bytes(0x5a, small.size());
+            match a.grow(ptr, small, size) {
+                Ok(grown) => a.dealloc(grown, large),
+                Err(_) => {
+                    assert_eq!(
+                        *ptr.as_ptr().add(63),
+                        0x5a,
+                        "failing to grow lost the contents"
+                    );
+                    a.dealloc(ptr, small);
+                }
+            }
+        }
+    }
+}
+
+/// An operation of a random sequence. Allocations are referred to by their index among the
+/// live ones, modulo their number, so every sequence stays valid while it is shrunk.
+#[derive(Clone, Debug)]
+enum Operation {
+    Alloc {
+        size: usize,
+        align_shift: usize,
+        zeroed: bool,
+    },
+    Dealloc {
+        index: usize,
+    },
+    Grow {
+        index: usize,
+        extra: usize,
+    },
+    Shrink {
+        index: usize,
+        less: usize,
+    },
+}
+
+impl Shrink for Operation {
+    fn shrink(&self) -> Vec<Self> {
+        match *self {
+            Operation::Alloc {
+                size,
+                align_shift,
+                zeroed,
+            } => {
+                let sizes = size.shrink().into_iter().map(|size| Operation::Alloc {
+                    size,
+                    align_shift,
+                    zeroed,
+                });
+                let aligns = align_shift
+                    .shrink()
+                    .into_iter()
+                    .map(|align_shift| Operation::Alloc {
+                        size,
+                        align_shift,
+                        zeroed,
+                    });
+                let zeroed = zeroed.shrink().into_iter().map(|zeroed| Operation::Alloc {
+                    size,
+                    align_shift,
+                    zeroed,
+                });
+                sizes.chain(aligns).chain(zeroed).collect()
+            }
+            Operation::Dealloc { index } => index
+                .shrink()
+                .into_iter()
+                .map(|index| Operation::Dealloc { index })
+                .collect(),
+            Operation::Grow { index, extra } => {
+                let mut candidates: Vec<_> = index
+                    .shrink()
+                    .into_iter()
+                    .map(|index| Operation::Grow { index, extra })
+                    .collect();
+                candidates.extend(
+                    extra
+                        .shrink()
+                        .into_iter()
+                        .filter(|extra| *extra > 0)
+                        .map(|extra| Operation::Grow { index, extra }),
+                );
+                candidates
+            }
+            Operation::Shrink { index, less } => {
+                let mut candidates: Vec<_> = index
+                    .shrink()
+                    .into_iter()
+                    .map(|index| Operation::Shrink { index, less })
+                    .collect();
+                candidates.extend(
+                    less.shrink()
+                        .into_iter()
+                        .filter(|less| *less > 0)
+                        .map(|less| Operation::Shrink { index, less }),
+                );
+                candidates
+            }
+        }
+    }
+}
+
+fn operations(rng: &mut ChaCha8Rng) -> Vec<Operation> {
+    let size = |rng: &mut ChaCha8Rng| match rng.gen_range(0, 4) {
+        0 => 0,
+        1 => rng.gen_range(1, 17),
+        2 => rng.gen_range(1, 257),
+        _ => rng.gen_range(1, 4097),
+    };
+    (0..rng.gen_range(0, MAX_OPERATIONS + 1))
+        .map(|_| match rng.gen_range(0, 5) {
+            0 | 1 => Operation::Alloc {
+                size: size(rng),
+                align_shift: rng.gen_range(0, 8),
+                zeroed: rng.gen_bool(0.25),
+            },
+            2 => Operation::Dealloc {
+                index: rng.gen_range(0, MAX_OPERATIONS),
+            },
+            3 => Operation::Grow {
+                index: rng.gen_range(0, MAX_OPERATIONS),
+                extra: size(rng).max(1),
+            },
+            _ => Operation::Shrink {
+                index: rng.gen_range(0, MAX_OPERATIONS),
+                less: size(rng).max(1),
+            },
+        })
+        .collect()
+}
+
+/// A live allocation of a sequence, filled with its own byte.
+struct Allocation {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Allocation {
+    /// Checks that the first `size` bytes still hold the fill byte.
+    fn check(&self, size: usize, step: usize) -> Result<(), String> {
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), size) };
+        match bytes.iter().position(|byte| *byte != self.fill) {
+            Some(offset) => Err(format!(
+                "step {}: byte {} of {:?} at {:p} was overwritten",
+                step, offset, self.layout, self.ptr
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn fill(&self, from: usize) {
+        let size = self.layout.size().saturating_sub(from);
+        unsafe { self.ptr.as_ptr().add(from).write_bytes(self.fill, size) };
+    }
+}
+
+/// Runs `operations` through `a`, checking every pointer with `Verifying`, and checking that
+/// no allocation loses its contents. Calls that fail are allowed, since allocators may run
+/// out of memory, but they must leave the allocation intact.
+fn run_operations<A: AllocRefV2>(a: A, operations: &[Operation]) -> Result<(), String> {
+    let verifying = Verifying::new(a);
+    let mut live: Vec<Allocation> = Vec::new();
+    let mut result = Ok(());
+    for (step, operation) in operations.iter().enumerate() {
+        match *operation {
+            Operation::Alloc {
+                size,
+                align_shift,
+                zeroed,
+            } => {
+                let layout = layout(size, 1 << align_shift);
+                let allocation = if zeroed {
+                    verifying.alloc_zeroed(layout)
+                } else {
+                    verifying.alloc(layout)
+                };
+                if let Ok(ptr) = allocation {
+                    let allocation = Allocation {
+                        ptr,
+                        layout,
+                        fill: step as u8,
+                    };
+                    allocation.fill(0);
+                    live.push(allocation);
+                }
+            }
+            Operation::Dealloc { .. } | Operation::Grow { .. } | Operation::Shrink { .. }
+                if live.is_empty() => {}
+            Operation::Dealloc { index: i } => {
+                let allocation = live.swap_remove(i % live.len());
+                result = result.and(allocation.check(allocation.layout.size(), step));
+                unsafe { verifying.dealloc(allocation.ptr, allocation.layout) };
+            }
+            Operation::Grow { index: i, extra } => {
+                let index = i % live.len();
+                let allocation = &mut live[index];
+                let (old, new_size) = (allocation.layout, allocation.layout.size() + extra);
+                if let Ok(ptr) = unsafe { verifying.grow(allocation.ptr, old, new_size) } {
+                    allocation.ptr = ptr;
+                    allocation.layout = layout(new_size, old.align());
+                    result = result.and(allocation.check(old.size(), step));
+                    allocation.fill(old.size());
+                }
+            }
+            Operation::Shrink { index: i, less } => {
+                let index = i % live.len();
+                let allocation = &mut live[index];
+                let old = allocation.layout;
+                if old.size() > 0 {
+                    let new_size = old.size() - less.min(old.size());
+                    if let Ok(ptr) = unsafe { verifying.shrink(allocation.ptr, old, new_size) } {
+                        allocation.ptr = ptr;
+                        allocation.layout = layout(new_size, old.align());
+                        result = result.and(allocation.check(new_size, step));
+                    }
+                }
+            }
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    for allocation in live {
+        unsafe { verifying.dealloc(allocation.ptr, allocation.layout) };
+    }
+
+    let verification = verifying.verification();
+    if !verification.passed() {
+        return Err(verification.to_string());
+    }
+    result
+}
+
+/// Runs the whole suite through `a`. Each random sequence runs through a fresh allocator from
+/// `run`, so earlier sequences cannot exhaust it, and a failure reproduces while it is shrunk.
+fn suite<A, F>(a: A, mut run: F)
+where
+    A: AllocRefV2,
+    F: FnMut(&[Operation]) -> Result<(), String>,
+{
+    zsts_for_every_alignment(a);
+    large_sizes(a);
+    property::check(&Config::default(), operations, |operations: &Vec<_>| {
+        run(operations)
+    });
+}
+
+#[test]
+fn non_zero_layout_conversion() {
+    for zst in zst_layouts() {
+        assert!(NonZeroLayout::try_from(zst).is_err(), "{:?}", zst);
+        assert!(non_zero(zst).is_err(), "{:?}", zst);
+    }
+
+    let max = isize::MAX as usize;
+    for &(size, align) in &[(1, 1), (1, 1 << 12), (3, 8), (max, 1), (max - 7, 8)] {
+        let layout = layout(size, align);
+        let non_zero = non_zero(layout).unwrap();
+        assert_eq!(Layout::from(non_zero), layout);
+        assert_eq!(
+            Layout::from(NonZeroLayout::try_from(layout).unwrap()),
+            layout
+        );
+    }
+
+    // A zero-sized layout is dispatched to `alloc_zst` instead of failing the conversion.
+    let ptr = AllocRefV2::alloc(Global, layout(0, 64)).unwrap();
+    assert_eq!(ptr.as_ptr() as usize, 64);
+}
+
+#[test]
+fn global() {
+    suite(Global, |operations| run_operations(Global, operations));
+}
+
+#[test]
+fn system() {
+    suite(System, |operations| run_operations(System, operations));
+}
+
+#[test]
+fn malloc() {
+    suite(Malloc, |operations| run_operations(Malloc, operations));
+}
+
+#[test]
+fn posix_memalign() {
+    suite(PosixMemalign, |operations| {
+        run_operations(PosixMemalign, operations)
+    });
+}
+
+#[test]
+fn aligned_alloc() {
+    suite(AlignedAlloc, |operations| {
+        run_operations(AlignedAlloc, operations)
+    });
+}
+
+#[test]
+fn bump() {
+    suite(&Bump::new(), |operations| {
+        run_operations(&Bump::new(), operations)
+    });
+}
+
+#[test]
+fn free_list() {
+    let new = || FreeList::with_capacity(CAPACITY);
+    suite(&new(), |operations| run_operations(&new(), operations));
+}
+
+/// A free list is sized to hold every layout of a workload, whatever the rounding of its blocks.
+#[test]
+fn free_list_holds_every_workload() {
+    for &(size_dist, align_dist) in &[
+        ("uniform:1..16", "pow2:1..8"),
+        ("uniform:1..200", "pow2:1..256"),
+        ("table:1=1,17=1,33=1", "table:1=1,4096=1"),
+    ] {
+        for &mode in Mode::ALL {
+            for &sizes in Sizes::ALL {
+                let scenario = Scenario {
+                    iters: 10_000,
+                    seed: 1,
+                    size_dist: size_dist.parse().unwrap(),
+                    align_dist: align_dist.parse().unwrap(),
+                    allocator: Allocator::FreeList,
+                    sizes,
+                    mode,
+                    ..Options::default().scenario()
+                };
+                let workload = Workload::new(&scenario);
+                let stats = track(&free_list_for(&workload), &scenario, &workload);
+                assert_eq!(stats.failures, 0, "{}", scenario);
+            }
+        }
+    }
+}
+
+#[test]
+fn slab() {
+    suite(&Slab::new(), |operations| {
+        run_operations(&Slab::new(), operations)
+    });
+}
+
+#[test]
+fn pool() {
+    let new = || Pool::new(layout(8192, 128), MAX_OPERATIONS).unwrap();
+    suite(&new(), |operations| run_operations(&new(), operations));
+}
+
+#[test]
+fn linear() {
+    let mut buffer = vec![MaybeUninit::uninit(); CAPACITY];
+    suite(&Linear::new(&mut buffer), |operations| {
+        let mut buffer = vec![MaybeUninit::uninit(); CAPACITY];
+        run_operations(&Linear::new(&mut buffer), operations)
+    });
+}
@@ -0,0 +1,693 @@
+//! Event counters around the timed regions: instructions, branches and cache misses from the
+//! hardware, and page faults and context switches from the kernel. They are read through
+//! `perf_event_open` on Linux. Hardware events are often unavailable, e.g. in a VM; the software
+//! events are then still counted, and fall back to `getrusage` if `perf_event_open` is not
+//! permitted at all.
+//!
+//! Like the clock, the counters are selected once for the whole process. They count the thread
+//! that selected them, and only between `start` and `stop`, which the clock calls at the
+//! boundaries of every timed region.
+
+use crate::scenario::Choice;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The number of counted events.
+const EVENTS: usize = 6;
+
+/// Whether the selected counters include events of `perf_event_open`.
+static PERF: AtomicBool = AtomicBool::new(false);
+
+/// Whether the selected counters include events of `getrusage`.
+static RUSAGE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The counters that this thread selected, if any.
+    static SELECTED: RefCell<Option<Selected>> = RefCell::default();
+}
+
+/// The counters of a thread.
+struct Selected {
+    /// Where every event is counted, in the order of `Event::ALL`.
+    sources: Vec<Source>,
+    /// The `getrusage` reading at the start of the current region.
+    rusage_start: [u64; 2],
+    /// The `getrusage` counts of all regions so far.
+    rusage_total: [u64; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counters {
+    None,
+    Perf,
+    Software,
+}
+
+impl Choice for Counters {
+    const WHAT: &'static str = "counter set";
+    const ALL: &'static [Self] = &[Counters::None, Counters::Perf, Counters::Software];
+
+    fn name(self) -> &'static str {
+        match self {
+            Counters::None => "none",
+            Counters::Perf => "perf",
+            Counters::Software => "software",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Counters::None => "only report timings",
+            Counters::Perf => "hardware and software events, or only the latter without a PMU",
+            Counters::Software => "only page faults and context switches",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Instructions,
+    Branches,
+    BranchMisses,
+    CacheMisses,
+    PageFaults,
+    ContextSwitches,
+}
+
+impl Event {
+    pub const ALL: [Event; EVENTS] = [
+        Event::Instructions,
+        Event::Branches,
+        Event::BranchMisses,
+        Event::CacheMisses,
+        Event::PageFaults,
+        Event::ContextSwitches,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Instructions => "instructions",
+            Event::Branches => "branches",
+            Event::BranchMisses => "branch-misses",
+            Event::CacheMisses => "cache-misses",
+            Event::PageFaults => "page-faults",
+            Event::ContextSwitches => "context-switches",
+        }
+    }
+
+    /// The name of the event in output records.
+    pub fn field(self) -> &'static str {
+        match self {
+            Event::Instructions => "instructions_per_alloc",
+            Event::Branches => "branches_per_alloc",
+            Event::BranchMisses => "branch_misses_per_alloc",
+            Event::CacheMisses => "cache_misses_per_alloc",
+            Event::PageFaults => "page_faults_per_alloc",
+            Event::ContextSwitches => "context_switches_per_alloc",
+        }
+    }
+
+    fn is_hardware(self) -> bool {
+        match self {
+            Event::Instructions | Event::Branches | Event::BranchMisses | Event::CacheMisses => {
+                true
+            }
+            Event::PageFaults | Event::ContextSwitches => false,
+        }
+    }
+
+    /// The type and config of the event for `perf_event_open`.
+    fn perf_event(self) -> (u32, u64) {
+        match self {
+            Event::Instructions => (perf::TYPE_HARDWARE, perf::HW_INSTRUCTIONS),
+            Event::Branches => (perf::TYPE_HARDWARE, perf::HW_BRANCH_INSTRUCTIONS),
+            Event::BranchMisses => (perf::TYPE_HARDWARE, perf::HW_BRANCH_MISSES),
+            Event::CacheMisses => (perf::TYPE_HARDWARE, perf::HW_CACHE_MISSES),
+            Event::PageFaults => (perf::TYPE_SOFTWARE, perf::SW_PAGE_FAULTS),
+            Event::ContextSwitches => (perf::TYPE_SOFTWARE, perf::SW_CONTEXT_SWITCHES),
+        }
+    }
+
+    /// The index of the event in a `getrusage` reading, if it has one.
+    fn rusage_index(self) -> Option<usize> {
+        match self {
+            Event::PageFaults => Some(0),
+            Event::ContextSwitches => Some(1),
+            _ => None,
+        }
+    }
+}
+
+/// Where an event is counted.
+enum Source {
+    Perf { file: File, user_only: bool },
+    Rusage,
+    Unavailable(String),
+}
+
+impl Source {
+    /// Opens a counter for `event`, including the kernel if that is permitted.
+    fn open(event: Event) -> Self {
+        let (kind, config) = event.perf_event();
+        let opened = perf::open(kind, config, false)
+            .map(|file| (file, false))
+            .or_else(|_| perf::open(kind, config, true).map(|file| (file, true)));
+        match opened {
+            Ok((file, user_only)) => Source::Perf { file, user_only },
+            Err(_) if event.rusage_index().is_some() && rusage::read().is_some() => Source::Rusage,
+            Err(error) => Source::Unavailable(format!("unavailable: {}", error)),
+        }
+    }
+
+    /// The count of the event since it was opened, or NaN if it was never counted.
+    fn read(&self, event: Event, rusage_total: [u64; 2]) -> f64 {
+        match self {
+            // A counter that shared the PMU with others was only scheduled part of the time, so
+            // its count is extrapolated to the time it was enabled.
+            Source::Perf { file, .. } => match perf::read(file) {
+                Ok([_, 0, _]) => 0.0,
+                Ok([_, _, 0]) | Err(_) => f64::NAN,
+                Ok([count, enabled, running]) => count as f64 * enabled as f64 / running as f64,
+            },
+            Source::Rusage => rusage_total[event.rusage_index().unwrap()] as f64,
+            Source::Unavailable(_) => f64::NAN,
+        }
+    }
+
+    fn describe(&self) -> &str {
+        match self {
+            Source::Perf {
+                user_only: false, ..
+            } => "perf_event_open",
+            Source::Perf {
+                user_only: true, ..
+            } => "perf_event_open, user space only",
+            Source::Rusage => "getrusage",
+            Source::Unavailable(reason) => reason,
+        }
+    }
+}
+
+/// Opens the counters of `counters` on this thread, for the rest of the process. Hardware events
+/// that cannot be counted are reported as unavailable. Selecting `Counters::None` closes the
+/// counters again.
+pub fn select(counters: Counters) -> Result<(), String> {
+    if counters == Counters::None {
+        PERF.store(false, Ordering::Relaxed);
+        RUSAGE.store(false, Ordering::Relaxed);
+        SELECTED.with(|selected| *selected.borrow_mut() = None);
+        return Ok(());
+    }
+    let mut sources: Vec<Source> = Event::ALL
+        .iter()
+        .map(|&event| match counters {
+            Counters::Software if event.is_hardware() => {
+                Source::Unavailable("not selected".to_owned())
+            }
+            _ => Source::open(event),
+        })
+        .collect();
+    let is_perf = |source: &Source| match source {
+        Source::Perf { .. } => true,
+        Source::Rusage | Source::Unavailable(_) => false,
+    };
+    if sources.iter().any(is_perf) {
+        if let Err(error) = perf::enable().and_then(|()| perf::disable()) {
+            lose_perf(&mut sources, &error);
+        }
+    }
+    let unavailable = |source: &Source| match source {
+        Source::Unavailable(_) => true,
+        Source::Perf { .. } | Source::Rusage => false,
+    };
+    if sources.iter().all(unavailable) {
+        return Err(format!(
+            "The counter set '{}' is not available on this platform.",
+            counters.name()
+        ));
+    }
+
+    let uses = |rusage: bool| {
+        sources.iter().any(|source| match source {
+            Source::Perf { .. } => !rusage,
+            Source::Rusage => rusage,
+            Source::Unavailable(_) => false,
+        })
+    };
+    PERF.store(uses(false), Ordering::Relaxed);
+    RUSAGE.store(uses(true), Ordering::Relaxed);
+    SELECTED.with(|selected| {
+        *selected.borrow_mut() = Some(Selected {
+            sources,
+            rusage_start: [0; 2],
+            rusage_total: [0; 2],
+        })
+    });
+    Ok(())
+}
+
+/// Starts counting, if counters are selected.
+#[inline(always)]
+pub fn start() {
+    if PERF.load(Ordering::Relaxed) {
+        if let Err(error) = perf::enable() {
+            perf_failed(&error);
+        }
+    }
+    if RUSAGE.load(Ordering::Relaxed) {
+        let now = rusage::read().unwrap_or([0; 2]);
+        with_selected(|selected| selected.rusage_start = now);
+    }
+}
+
+/// Stops counting, if counters are selected.
+#[inline(always)]
+pub fn stop() {
+    if RUSAGE.load(Ordering::Relaxed) {
+        let now = rusage::read().unwrap_or([0; 2]);
+        with_selected(|selected| {
+            for (i, total) in selected.rusage_total.iter_mut().enumerate() {
+                *total += now[i].saturating_sub(selected.rusage_start[i]);
+            }
+        });
+    }
+    if PERF.load(Ordering::Relaxed) {
+        if let Err(error) = perf::disable() {
+            perf_failed(&error);
+        }
+    }
+}
+
+/// Marks the counters of `perf_event_open` as unavailable, because they cannot be enabled or
+/// disabled.
+fn lose_perf(sources: &mut [Source], error: &io::Error) {
+    for source in sources {
+        if let Source::Perf { .. } = source {
+            *source = Source::Unavailable(format!("unavailable: prctl failed: {}", error));
+        }
+    }
+}
+
+/// Stops using `perf_event_open` after enabling or disabling its counters failed, which leaves
+/// their counts incomplete.
+#[cold]
+fn perf_failed(error: &io::Error) {
+    PERF.store(false, Ordering::Relaxed);
+    with_selected(|selected| lose_perf(&mut selected.sources, error));
+}
+
+/// Calls `f` with the counters of this thread, if it selected any.
+fn with_selected<T, F: FnOnce(&mut Selected) -> T>(f: F) -> Option<T> {
+    SELECTED.with(|selected| selected.borrow_mut().as_mut().map(f))
+}
+
+/// The counts of every event so far, if counters are selected.
+fn read() -> Option<[f64; EVENTS]> {
+    with_selected(|selected| {
+        let mut counts = [0.0; EVENTS];
+        for (i, source) in selected.sources.iter().enumerate() {
+            counts[i] = source.read(Event::ALL[i], selected.rusage_total);
+        }
+        counts
+    })
+}
+
+/// The counts over a number of runs.
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    runs: usize,
+    counts: [f64; EVENTS],
+}
+
+impl Totals {
+    fn per_run(&self, i: usize) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.counts[i] / self.runs as f64
+        }
+    }
+}
+
+/// The counts of a measurement, split into the runs of the samples and of their baseline.
+#[derive(Debug, Default)]
+pub struct Tally {
+    samples: Cell<Totals>,
+    baseline: Cell<Totals>,
+}
+
+impl Tally {
+    /// Runs a sample, and adds what it counted.
+    pub fn sample<T, F: FnOnce() -> T>(&self, run: F) -> T {
+        count(&self.samples, run)
+    }
+
+    /// Runs a baseline, and adds what it counted.
+    pub fn baseline<T, F: FnOnce() -> T>(&self, run: F) -> T {
+        count(&self.baseline, run)
+    }
+
+    /// The mean counts of a sample with the mean counts of the baseline subtracted, divided by
+    /// the `allocations` of a sample. None if no counters are selected.
+    pub fn report(&self, allocations: usize) -> Option<Report> {
+        let (samples, baseline) = (self.samples.get(), self.baseline.get());
+        let per_allocation = |count: f64| count / allocations.max(1) as f64;
+        let rows = with_selected(|selected| {
+            Event::ALL
+                .iter()
+                .zip(&selected.sources)
+                .enumerate()
+                .map(|(i, (&event, source))| Row {
+                    event,
+                    count: per_allocation(samples.per_run(i) - baseline.per_run(i)),
+                    baseline: per_allocation(baseline.per_run(i)),
+                    source: source.describe().to_owned(),
+                })
+                .collect()
+        })?;
+        Some(Report {
+            runs: samples.runs,
+            rows,
+        })
+    }
+}
+
+fn count<T, F: FnOnce() -> T>(totals: &Cell<Totals>, run: F) -> T {
+    let before = match read() {
+        Some(counts) => counts,
+        None => return run(),
+    };
+    let result = run();
+    let after = read().unwrap();
+
+    let mut sum = totals.get();
+    sum.runs += 1;
+    for i in 0..EVENTS {
+        sum.counts[i] += after[i] - before[i];
+    }
+    totals.set(sum);
+    result
+}
+
+struct Row {
+    event: Event,
+    count: f64,
+    baseline: f64,
+    source: String,
+}
+
+/// The events that were counted per allocation, in the order of `Event::ALL`.
+pub struct Report {
+    /// The number of runs, including the warmup, that the counts are averaged over.
+    runs: usize,
+    rows: Vec<Row>,
+}
+
+impl Report {
+    /// The count of `event` per allocation, or NaN if it was not counted.
+    pub fn per_allocation(&self, event: Event) -> f64 {
+        self.rows
+            .iter()
+            .find(|row| row.event == event)
+            .map_or(f64::NAN, |row| row.count)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |value: f64| {
+            if value.is_nan() {
+                "n/a".to_owned()
+            } else {
+                format!("{:.3}", value)
+            }
+        };
+        write!(
+            f,
+            "counters: per allocation over {} runs, with the baseline subtracted\n    \
+             {:<18}{:>12}{:>12}  source",
+            self.runs, "event", "count", "baseline"
+        )?;
+        for row in &self.rows {
+            write!(
+                f,
+                "\n    {:<18}{:>12}{:>12}  {}",
+                row.event.name(),
+                count(row.count),
+                count(row.baseline),
+                row.source
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod perf {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::mem;
+    use std::os::raw::{c_int, c_long, c_ulong};
+    use std::os::unix::io::FromRawFd;
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 298;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 241;
+
+    pub const TYPE_HARDWARE: u32 = 0;
+    pub const TYPE_SOFTWARE: u32 = 1;
+    pub const HW_INSTRUCTIONS: u64 = 1;
+    pub const HW_CACHE_MISSES: u64 = 3;
+    pub const HW_BRANCH_INSTRUCTIONS: u64 = 4;
+    pub const HW_BRANCH_MISSES: u64 = 5;
+    pub const SW_PAGE_FAULTS: u64 = 2;
+    pub const SW_CONTEXT_SWITCHES: u64 = 3;
+
+    const FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+    const FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+    const FLAG_DISABLED: u64 = 1 << 0;
+    const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+    const FLAG_FD_CLOEXEC: c_ulong = 1 << 3;
+
+    const PR_TASK_PERF_EVENTS_DISABLE: c_int = 31;
+    const PR_TASK_PERF_EVENTS_ENABLE: c_int = 32;
+
+    /// The first version of `struct perf_event_attr`, which every kernel accepts.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Attr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn prctl(option: c_int, ...) -> c_int;
+    }
+
+    /// Opens a disabled counter of the calling thread on any CPU.
+    pub fn open(kind: u32, config: u64, user_only: bool) -> io::Result<File> {
+        let attr = Attr {
+            kind,
+            size: mem::size_of::<Attr>() as u32,
+            config,
+            read_format: FORMAT_TOTAL_TIME_ENABLED | FORMAT_TOTAL_TIME_RUNNING,
+            flags: FLAG_DISABLED
+                | FLAG_EXCLUDE_HV
+                | if user_only { FLAG_EXCLUDE_KERNEL } else { 0 },
+            ..Attr::default()
+        };
+        let (pid, cpu, group): (c_int, c_int, c_int) = (0, -1, -1);
+        let fd = unsafe {
+            syscall(
+                SYS_PERF_EVENT_OPEN,
+                &attr as *const Attr,
+                pid,
+                cpu,
+                group,
+                FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(unsafe { File::from_raw_fd(fd as c_int) })
+        }
+    }
+
+    /// Reads the count of a counter, and the nanoseconds that it was enabled and running.
+    pub fn read(mut file: &File) -> io::Result<[u64; 3]> {
+        let mut buffer = [0; 24];
+        file.read_exact(&mut buffer)?;
+        let mut values = [0; 3];
+        for (value, bytes) in values.iter_mut().zip(buffer.chunks(8)) {
+            let mut word = [0; 8];
+            word.copy_from_slice(bytes);
+            *value = u64::from_ne_bytes(word);
+        }
+        Ok(values)
+    }
+
+    /// Enables every counter that this thread opened.
+    #[inline(always)]
+    pub fn enable() -> io::Result<()> {
+        task_events(PR_TASK_PERF_EVENTS_ENABLE)
+    }
+
+    /// Disables every counter that this thread opened.
+    #[inline(always)]
+    pub fn disable() -> io::Result<()> {
+        task_events(PR_TASK_PERF_EVENTS_DISABLE)
+    }
+
+    #[inline(always)]
+    fn task_events(option: c_int) -> io::Result<()> {
+        if unsafe { prctl(option) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod perf {
+    use std::fs::File;
+    use std::io;
+
+    pub const TYPE_HARDWARE: u32 = 0;
+    pub const TYPE_SOFTWARE: u32 = 1;
+    pub const HW_INSTRUCTIONS: u64 = 0;
+    pub const HW_CACHE_MISSES: u64 = 0;
+    pub const HW_BRANCH_INSTRUCTIONS: u64 = 0;
+    pub const HW_BRANCH_MISSES: u64 = 0;
+    pub const SW_PAGE_FAULTS: u64 = 0;
+    pub const SW_CONTEXT_SWITCHES: u64 = 0;
+
+    pub fn open(_kind: u32, _config: u64, _user_only: bool) -> io::Result<File> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "perf_event_open is not available on this platform",
+        ))
+    }
+
+    pub fn read(_file: &File) -> io::Result<[u64; 3]> {
+        unreachable!("no counters are opened on this platform")
+    }
+
+    pub fn enable() -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn disable() -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod rusage {
+    use std::mem;
+    use std::os::raw::{c_int, c_long};
+
+    const RUSAGE_THREAD: c_int = 1;
+
+    #[repr(C)]
+    struct Rusage {
+        utime: [c_long; 2],
+        stime: [c_long; 2],
+        maxrss: c_long,
+        ixrss: c_long,
+        idrss: c_long,
+        isrss: c_long,
+        minflt: c_long,
+        majflt: c_long,
+        nswap: c_long,
+        inblock: c_long,
+        oublock: c_long,
+        msgsnd: c_long,
+        msgrcv: c_long,
+        nsignals: c_long,
+        nvcsw: c_long,
+        nivcsw: c_long,
+    }
+
+    extern "C" {
+        fn getrusage(who: c_int, usage: *mut Rusage) -> c_int;
+    }
+
+    /// The page faults and context switches of the calling thread so far.
+    #[inline(always)]
+    pub fn read() -> Option<[u64; 2]> {
+        let mut usage: Rusage = unsafe { mem::zeroed() };
+        if unsafe { getrusage(RUSAGE_THREAD, &mut usage) } != 0 {
+            return None;
+        }
+        Some([
+            (usage.minflt + usage.majflt) as u64,
+            (usage.nvcsw + usage.nivcsw) as u64,
+        ])
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod rusage {
+    pub fn read() -> Option<[u64; 2]> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, start, stop, Counters, Event, Tally};
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn software_events_count_page_faults_per_allocation() {
+        // Pages of a huge page apart, so that every write faults, whether or not the kernel
+        // backs the memory with transparent huge pages.
+        const PAGES: usize = 32;
+        const STRIDE: usize = 2 << 20;
+        select(Counters::Software).unwrap();
+
+        let tally = Tally::default();
+        tally.baseline(|| {
+            start();
+            stop();
+        });
+        tally.sample(|| {
+            // Larger than the most that malloc serves from its heap, so that it is mapped fresh
+            // instead of reusing pages that other tests faulted in.
+            let mut memory = Vec::<u8>::with_capacity(PAGES * STRIDE);
+            start();
+            for page in 0..PAGES {
+                unsafe { memory.as_mut_ptr().add(page * STRIDE).write_volatile(1) };
+            }
+            stop();
+        });
+
+        let report = tally.report(PAGES).unwrap();
+        // The counters are selected for the whole process, so other tests must not see them.
+        select(Counters::None).unwrap();
+        let faults = report.per_allocation(Event::PageFaults);
+        assert!(faults > 0.5 && faults < 2.0, "{}", report);
+        assert!(report.per_allocation(Event::Instructions).is_nan());
+    }
+}
@@ -0,0 +1,510 @@
+use rand::Rng;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// The largest supported alignment.
+const MAX_ALIGN: usize = 1 << 30;
+
+/// The largest amount by which the grow and shrink modes grow a layout.
+pub const MAX_GROWTH: usize = 1024;
+
+/// The largest supported size. A layout of this size can be grown by `MAX_GROWTH` and aligned to
+/// `MAX_ALIGN` without exceeding `isize::MAX`, as `Layout` requires.
+pub const MAX_SIZE: usize = isize::MAX as usize - MAX_ALIGN + 1 - MAX_GROWTH;
+
+/// Describes the syntax of size and alignment distributions, for the usage message.
+pub const SYNTAX: &str = "\
+SIZE DISTRIBUTIONS (--size-dist), used for non-zero-sized layouts:
+    uniform:MIN..MAX           Uniformly distributed in MIN..=MAX bytes
+    log-uniform:MIN..MAX       Uniformly distributed orders of magnitude in MIN..=MAX bytes
+    normal:MEAN,STD_DEV        Normally distributed, clamped to at least 1 byte
+    geometric:P                Geometrically distributed with success probability P, from 1 byte
+    power-law:ALPHA,MIN..MAX   Bounded Pareto distribution with exponent ALPHA in MIN..=MAX bytes
+    table:SIZE=WEIGHT,...      Explicit sizes, chosen proportionally to their weights
+Sizes are limited to slightly less than isize::MAX bytes, leaving room for any alignment and growth.
+
+ALIGNMENT DISTRIBUTIONS (--align-dist):
+    fixed:ALIGN                Always ALIGN
+    pow2:MIN..MAX              Uniformly distributed powers of two in MIN..=MAX
+    table:ALIGN=WEIGHT,...     Explicit alignments, chosen proportionally to their weights";
+
+/// A set of values, each chosen with a probability proportional to its weight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightedTable {
+    entries: Vec<(usize, f64)>,
+    /// Running totals of the weights, for sampling through binary search.
+    cumulative: Vec<f64>,
+}
+
+impl WeightedTable {
+    fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        let total = self.cumulative[self.cumulative.len() - 1];
+        let target = rng.gen::<f64>() * total;
+        // Find the first entry whose running total exceeds the target. Entries with a weight of
+        // zero repeat the running total of their predecessor, so they are never chosen.
+        let index = self.cumulative.partition_point(|&sum| sum <= target);
+        self.entries[index.min(self.entries.len() - 1)].0
+    }
+
+    fn values(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().map(|(value, _)| *value)
+    }
+}
+
+impl FromStr for WeightedTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for entry in s.split(',') {
+            let equals = entry
+                .find('=')
+                .ok_or_else(|| format!("Expected VALUE=WEIGHT, found '{}'.", entry))?;
+            let value = parse_number(&entry[..equals])?;
+            let weight: f64 = parse_number(&entry[equals + 1..])?;
+            if !(weight >= 0.0 && weight.is_finite()) {
+                return Err(format!("Invalid weight in '{}'.", entry));
+            }
+            entries.push((value, weight));
+        }
+
+        let cumulative: Vec<f64> = entries
+            .iter()
+            .scan(0.0, |sum, (_, weight)| {
+                *sum += weight;
+                Some(*sum)
+            })
+            .collect();
+        match cumulative.last() {
+            Some(total) if *total > 0.0 => {}
+            _ => return Err("A weighted table needs at least one positive weight.".to_owned()),
+        }
+
+        Ok(WeightedTable {
+            entries,
+            cumulative,
+        })
+    }
+}
+
+impl fmt::Display for WeightedTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (value, weight)) in self.entries.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}={}", value, weight)?;
+        }
+        Ok(())
+    }
+}
+
+/// The distribution of the sizes of non-zero-sized layouts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SizeDistribution {
+    Uniform { min: usize, max: usize },
+    LogUniform { min: usize, max: usize },
+    Normal { mean: f64, std_dev: f64 },
+    Geometric { p: f64 },
+    PowerLaw { alpha: f64, min: usize, max: usize },
+    Table(WeightedTable),
+}
+
+impl Default for SizeDistribution {
+    fn default() -> Self {
+        SizeDistribution::Uniform { min: 1, max: 1024 }
+    }
+}
+
+impl SizeDistribution {
+    /// Draws a size, which is always at least one byte and at most `MAX_SIZE`.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        let size = match self {
+            SizeDistribution::Uniform { min, max } => rng.gen_range(*min, *max + 1),
+            SizeDistribution::LogUniform { min, max } => {
+                let (low, high) = ((*min as f64).ln(), (*max as f64 + 1.0).ln());
+                let size = (low + rng.gen::<f64>() * (high - low)).exp() as usize;
+                size.max(*min).min(*max)
+            }
+            SizeDistribution::Normal { mean, std_dev } => {
+                // Box-Muller transform; `1 - gen()` lies in (0, 1], so the logarithm is finite.
+                let (u1, u2) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+                // Casting saturates, so sizes beyond `usize::MAX` are clamped below.
+                (mean + std_dev * z).round().max(1.0) as usize
+            }
+            SizeDistribution::Geometric { p } => {
+                if *p >= 1.0 {
+                    return 1;
+                }
+                let u = 1.0 - rng.gen::<f64>();
+                // `ln_1p` keeps tiny probabilities from rounding `1 - p` to one.
+                let failures = (u.ln() / (-p).ln_1p()).floor() as usize;
+                failures.saturating_add(1)
+            }
+            SizeDistribution::PowerLaw { alpha, min, max } => {
+                // Inverse transform sampling of the bounded Pareto distribution.
+                let (low, high) = (*min as f64, *max as f64);
+                let u = rng.gen::<f64>();
+                let size = low * (1.0 - u * (1.0 - (low / high).powf(*alpha))).powf(-1.0 / alpha);
+                (size as usize).max(*min).min(*max)
+            }
+            SizeDistribution::Table(table) => table.sample(rng),
+        };
+        size.min(MAX_SIZE)
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = split_kind(s)?;
+        let distribution = match kind {
+            "uniform" => {
+                let (min, max) = parse_range(params)?;
+                SizeDistribution::Uniform { min, max }
+            }
+            "log-uniform" => {
+                let (min, max) = parse_range(params)?;
+                SizeDistribution::LogUniform { min, max }
+            }
+            "normal" => {
+                let (mean, std_dev) = split_pair(params, ',')?;
+                let (mean, std_dev): (f64, f64) = (parse_number(mean)?, parse_number(std_dev)?);
+                if !(mean.is_finite() && std_dev >= 0.0 && std_dev.is_finite()) {
+                    return Err(format!("Invalid normal distribution '{}'.", s));
+                }
+                SizeDistribution::Normal { mean, std_dev }
+            }
+            "geometric" => {
+                let p: f64 = parse_number(params)?;
+                if !(p > 0.0 && p <= 1.0) {
+                    return Err(format!("The probability in '{}' must be in (0, 1].", s));
+                }
+                SizeDistribution::Geometric { p }
+            }
+            "power-law" => {
+                let (alpha, range) = split_pair(params, ',')?;
+                let alpha: f64 = parse_number(alpha)?;
+                if !(alpha > 0.0 && alpha.is_finite()) {
+                    return Err(format!("The exponent in '{}' must be positive.", s));
+                }
+                let (min, max) = parse_range(range)?;
+                SizeDistribution::PowerLaw { alpha, min, max }
+            }
+            "table" => {
+                let table: WeightedTable = params.parse()?;
+                if table.values().any(|size| size == 0 || size > MAX_SIZE) {
+                    return Err(format!(
+                        "Sizes in a table must be greater than zero and at most {}.",
+                        MAX_SIZE
+                    ));
+                }
+                SizeDistribution::Table(table)
+            }
+            _ => return Err(format!("Unknown size distribution '{}'.", kind)),
+        };
+        Ok(distribution)
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeDistribution::Uniform { min, max } => write!(f, "uniform:{}..{}", min, max),
+            SizeDistribution::LogUniform { min, max } => {
+                write!(f, "log-uniform:{}..{}", min, max)
+            }
+            SizeDistribution::Normal { mean, std_dev } => {
+                write!(f, "normal:{},{}", mean, std_dev)
+            }
+            SizeDistribution::Geometric { p } => write!(f, "geometric:{}", p),
+            SizeDistribution::PowerLaw { alpha, min, max } => {
+                write!(f, "power-law:{},{}..{}", alpha, min, max)
+            }
+            SizeDistribution::Table(table) => write!(f, "table:{}", table),
+        }
+    }
+}
+
+/// The distribution of layout alignments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlignDistribution {
+    Fixed(usize),
+    PowerOfTwo { min: usize, max: usize },
+    Table(WeightedTable),
+}
+
+impl Default for AlignDistribution {
+    fn default() -> Self {
+        AlignDistribution::PowerOfTwo { min: 1, max: 8 }
+    }
+}
+
+impl AlignDistribution {
+    /// Draws an alignment, which is always a power of two.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            AlignDistribution::Fixed(align) => *align,
+            AlignDistribution::PowerOfTwo { min, max } => {
+                let (low, high) = (min.trailing_zeros(), max.trailing_zeros());
+                1 << rng.gen_range(low, high + 1)
+            }
+            AlignDistribution::Table(table) => table.sample(rng),
+        }
+    }
+}
+
+impl FromStr for AlignDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = split_kind(s)?;
+        let distribution = match kind {
+            "fixed" => AlignDistribution::Fixed(parse_number(params)?),
+            "pow2" => {
+                let (min, max) = parse_range(params)?;
+                AlignDistribution::PowerOfTwo { min, max }
+            }
+            "table" => AlignDistribution::Table(params.parse()?),
+            _ => return Err(format!("Unknown alignment distribution '{}'.", kind)),
+        };
+
+        let invalid = match &distribution {
+            AlignDistribution::Fixed(align) => check_align(*align).err(),
+            AlignDistribution::PowerOfTwo { min, max } => {
+                check_align(*min).and(check_align(*max)).err()
+            }
+            AlignDistribution::Table(table) => {
+                table.values().map(check_align).find_map(Result::err)
+            }
+        };
+        match invalid {
+            Some(error) => Err(error),
+            None => Ok(distribution),
+        }
+    }
+}
+
+impl fmt::Display for AlignDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AlignDistribution::Fixed(align) => write!(f, "fixed:{}", align),
+            AlignDistribution::PowerOfTwo { min, max } => write!(f, "pow2:{}..{}", min, max),
+            AlignDistribution::Table(table) => write!(f, "table:{}", table),
+        }
+    }
+}
+
+fn check_align(align: usize) -> Result<(), String> {
+    if align.is_power_of_two() && align <= MAX_ALIGN {
+        Ok(())
+    } else {
+        Err(format!(
+            "Alignment {} is not a power of two of at most {}.",
+            align, MAX_ALIGN
+        ))
+    }
+}
+
+/// Splits `KIND:PARAMS`.
+fn split_kind(s: &str) -> Result<(&str, &str), String> {
+    split_pair(s, ':').map_err(|_| format!("Expected KIND:PARAMETERS, found '{}'.", s))
+}
+
+fn split_pair(s: &str, separator: char) -> Result<(&str, &str), String> {
+    match s.find(separator) {
+        Some(index) => Ok((&s[..index], &s[index + separator.len_utf8()..])),
+        None => Err(format!("Expected '{}' in '{}'.", separator, s)),
+    }
+}
+
+/// Parses an inclusive `MIN..MAX` range with `0 < MIN <= MAX <= MAX_SIZE`.
+fn parse_range(s: &str) -> Result<(usize, usize), String> {
+    let dots = s
+        .find("..")
+        .ok_or_else(|| format!("Expected MIN..MAX, found '{}'.", s))?;
+    let (min, max) = (parse_number(&s[..dots])?, parse_number(&s[dots + 2..])?);
+    if min == 0 || min > max || max > MAX_SIZE {
+        return Err(format!(
+            "Invalid range '{}'; expected 0 < MIN <= MAX <= {}.",
+            s, MAX_SIZE
+        ));
+    }
+    Ok((min, max))
+}
+
+fn parse_number<T: FromStr>(s: &str) -> Result<T, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("Invalid number '{}'.", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlignDistribution, SizeDistribution, MAX_ALIGN, MAX_GROWTH, MAX_SIZE};
+    use rand::rngs::mock::StepRng;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use std::alloc::Layout;
+
+    const SAMPLES: usize = 10_000;
+
+    const SIZE_DISTRIBUTIONS: [&str; 8] = [
+        "uniform:1..1024",
+        "uniform:7..7",
+        "log-uniform:16..65536",
+        "normal:100,30",
+        "geometric:0.25",
+        "geometric:1",
+        "power-law:1.5,8..4096",
+        "table:8=1,24=2.5,4096=0.5",
+    ];
+
+    const ALIGN_DISTRIBUTIONS: [&str; 4] =
+        ["fixed:16", "pow2:1..4096", "pow2:8..8", "table:1=1,64=3"];
+
+    #[test]
+    fn distributions_survive_a_display_round_trip() {
+        for s in SIZE_DISTRIBUTIONS
+            .iter()
+            .chain(&["normal:1e19,1", "geometric:1e-300"])
+        {
+            let distribution: SizeDistribution = s.parse().unwrap();
+            assert_eq!(
+                distribution.to_string().parse(),
+                Ok(distribution.clone()),
+                "{}",
+                s
+            );
+        }
+        for s in ALIGN_DISTRIBUTIONS.iter() {
+            let distribution: AlignDistribution = s.parse().unwrap();
+            assert_eq!(
+                distribution.to_string().parse(),
+                Ok(distribution.clone()),
+                "{}",
+                s
+            );
+        }
+        assert_eq!(
+            "uniform:1..1024"
+                .parse::<SizeDistribution>()
+                .unwrap()
+                .to_string(),
+            "uniform:1..1024"
+        );
+    }
+
+    #[test]
+    fn invalid_distributions_are_rejected() {
+        let sizes = [
+            "uniform",
+            "uniform:0..8",
+            "uniform:9..8",
+            "uniform:1..9223372036854775807",
+            "log-uniform:1..x",
+            "normal:1,-1",
+            "normal:inf,1",
+            "geometric:0",
+            "geometric:1.5",
+            "power-law:0,1..8",
+            "table:0=1",
+            "table:8=0",
+            "table:9223372036854775807=1",
+            "zipf:1",
+        ];
+        for s in sizes.iter() {
+            assert!(s.parse::<SizeDistribution>().is_err(), "{}", s);
+        }
+        let aligns = [
+            "fixed:3",
+            "fixed:0",
+            "pow2:1..6",
+            "table:12=1",
+            "fixed:2147483648",
+        ];
+        for s in aligns.iter() {
+            assert!(s.parse::<AlignDistribution>().is_err(), "{}", s);
+        }
+        assert!(format!("uniform:1..{}", MAX_SIZE)
+            .parse::<SizeDistribution>()
+            .is_ok());
+        assert!(format!("uniform:1..{}", MAX_SIZE + 1)
+            .parse::<SizeDistribution>()
+            .is_err());
+    }
+
+    #[test]
+    fn sizes_stay_within_their_bounds() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let bounds = [
+            (1, 1024),
+            (7, 7),
+            (16, 65536),
+            (1, MAX_SIZE),
+            (1, MAX_SIZE),
+            (1, 1),
+            (8, 4096),
+            (8, 4096),
+        ];
+        for (s, (min, max)) in SIZE_DISTRIBUTIONS.iter().zip(bounds.iter()) {
+            let distribution: SizeDistribution = s.parse().unwrap();
+            for _ in 0..SAMPLES {
+                let size = distribution.sample(&mut rng);
+                assert!(size >= *min && size <= *max, "{} sampled {}", s, size);
+            }
+        }
+        let table: SizeDistribution = "table:8=1,24=2.5,4096=0.5".parse().unwrap();
+        assert!((0..SAMPLES).all(|_| [8, 24, 4096].contains(&table.sample(&mut rng))));
+    }
+
+    #[test]
+    fn entries_without_weight_are_never_sampled() {
+        // `StepRng` returns a constant, and the top bit alone is sampled as exactly 0.5, which
+        // lands on the running total that the zero weight shares with its predecessor.
+        let half = 1 << 63;
+        let table: SizeDistribution = "table:8=1,16=0,32=1".parse().unwrap();
+        assert_eq!(table.sample(&mut StepRng::new(0, 0)), 8);
+        assert_eq!(table.sample(&mut StepRng::new(half, 0)), 32);
+        assert_eq!(table.sample(&mut StepRng::new(u64::MAX, 0)), 32);
+
+        let table: SizeDistribution = "table:8=0,16=1,32=0".parse().unwrap();
+        assert_eq!(table.sample(&mut StepRng::new(0, 0)), 16);
+        assert_eq!(table.sample(&mut StepRng::new(u64::MAX, 0)), 16);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let table: SizeDistribution = "table:8=0,16=1,24=0,32=2,40=0".parse().unwrap();
+        assert!((0..SAMPLES).all(|_| [16, 32].contains(&table.sample(&mut rng))));
+    }
+
+    #[test]
+    fn extreme_sizes_are_clamped_to_valid_layouts() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for s in ["normal:1e19,1", "normal:-1e19,1", "geometric:1e-300"].iter() {
+            let distribution: SizeDistribution = s.parse().unwrap();
+            for _ in 0..100 {
+                let size = distribution.sample(&mut rng);
+                assert!((1..=MAX_SIZE).contains(&size), "{} sampled {}", s, size);
+            }
+        }
+        // The largest size can still be grown and aligned to the largest alignment.
+        assert!(Layout::from_size_align(MAX_SIZE + MAX_GROWTH, MAX_ALIGN).is_ok());
+    }
+
+    #[test]
+    fn alignments_are_powers_of_two_within_their_bounds() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let bounds = [(16, 16), (1, 4096), (8, 8), (1, 64)];
+        for (s, (min, max)) in ALIGN_DISTRIBUTIONS.iter().zip(bounds.iter()) {
+            let distribution: AlignDistribution = s.parse().unwrap();
+            for _ in 0..SAMPLES {
+                let align = distribution.sample(&mut rng);
+                assert!(align.is_power_of_two(), "{} sampled {}", s, align);
+                assert!(align >= *min && align <= *max, "{} sampled {}", s, align);
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! An allocator wrapper that injects allocation failures, to exercise and measure the error
+//! paths of its callers.
+
+use crate::AllocRefV2;
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ptr::NonNull;
+use std::str::FromStr;
+
+/// Which calls `FailingAlloc` fails. Only calls that allocate can fail; deallocations always
+/// succeed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Failure {
+    /// Every `n`th call fails.
+    Every(u64),
+    /// Every call fails that would take the number of bytes allocated so far past the limit.
+    AfterBytes(u64),
+    /// Every call fails with the given probability.
+    Random(f64),
+}
+
+impl FromStr for Failure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colon = s
+            .find(':')
+            .ok_or_else(|| format!("Expected KIND:PARAMETER, found '{}'.", s))?;
+        let (kind, param) = (&s[..colon], s[colon + 1..].trim());
+        let invalid = || format!("Invalid parameter '{}' for '{}'.", param, kind);
+        match kind {
+            "every" => match param.parse() {
+                Ok(n) if n > 0 => Ok(Failure::Every(n)),
+                _ => Err(invalid()),
+            },
+            "after-bytes" => param
+                .parse()
+                .map(Failure::AfterBytes)
+                .map_err(|_| invalid()),
+            "random" => match param.parse() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Ok(Failure::Random(p)),
+                _ => Err(invalid()),
+            },
+            _ => Err(format!(
+                "Unknown failure '{}'; expected 'every', 'after-bytes' or 'random'.",
+                kind
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Every(n) => write!(f, "every:{}", n),
+            Failure::AfterBytes(limit) => write!(f, "after-bytes:{}", limit),
+            Failure::Random(p) => write!(f, "random:{}", p),
+        }
+    }
+}
+
+/// Fails the calls to `A` that `Failure` selects, without calling `A`, and delegates all other
+/// calls. The failures only depend on the sequence of calls and the seed, so a run can be
+/// repeated exactly.
+pub struct FailingAlloc<A> {
+    inner: A,
+    failure: Failure,
+    /// The number of calls since the last failure of `Failure::Every`.
+    calls: Cell<u64>,
+    /// The number of bytes allocated by successful calls.
+    bytes: Cell<u64>,
+    failures: Cell<u64>,
+    rng: RefCell<ChaCha8Rng>,
+}
+
+impl<A> FailingAlloc<A> {
+    pub fn new(inner: A, failure: Failure, seed: u64) -> Self {
+        FailingAlloc {
+            inner,
+            failure,
+            calls: Cell::new(0),
+            bytes: Cell::new(0),
+            failures: Cell::new(0),
+            rng: RefCell::new(ChaCha8Rng::seed_from_u64(seed)),
+        }
+    }
+
+    /// The number of calls that were failed so far.
+    #[cfg(test)]
+    fn failures(&self) -> u64 {
+        self.failures.get()
+    }
+
+    /// Decides whether a call that allocates `size` bytes fails.
+    fn fails(&self, size: usize) -> bool {
+        let fails = match self.failure {
+            Failure::Every(n) => {
+                let calls = self.calls.get() + 1;
+                self.calls.set(if calls == n { 0 } else { calls });
+                calls == n
+            }
+            Failure::AfterBytes(limit) => self.bytes.get() + size as u64 > limit,
+            Failure::Random(p) => self.rng.borrow_mut().gen_bool(p),
+        };
+        if fails {
+            self.failures.set(self.failures.get() + 1);
+        } else {
+            self.bytes.set(self.bytes.get() + size as u64);
+        }
+        fails
+    }
+
+    fn check(&self, layout: NonZeroLayout) -> Result<(), AllocErr> {
+        if self.fails(Layout::from(layout).size()) {
+            Err(AllocErr)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<A: AllocRef> AllocRef for &FailingAlloc<A> {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.check(layout)?;
+        AllocRef::alloc(self.inner, layout)
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.check(layout)?;
+        AllocRef::alloc_zeroed(self.inner, layout)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        AllocRef::dealloc(self.inner, ptr, layout)
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.check(new_layout)?;
+        AllocRef::realloc(self.inner, ptr, layout, new_layout)
+    }
+}
+
+impl<A: AllocRefV2> AllocRefV2 for &FailingAlloc<A> {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.check(layout)?;
+        self.inner.alloc_non_zst(layout)
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.check(layout)?;
+        self.inner.alloc_zeroed_non_zst(layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.inner.dealloc_non_zst(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.check(new_layout)?;
+        self.inner.grow_non_zst(ptr, layout, new_layout)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.check(new_layout)?;
+        self.inner.shrink_non_zst(ptr, layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailingAlloc, Failure};
+    use crate::cli::Options;
+    use crate::scenario::{Call, Choice, Mode, Scenario, Sizes};
+    use crate::tracking::Tracking;
+    use crate::{run_test, AllocRefV2, Workload};
+    use std::alloc::{Layout, System};
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 8).unwrap()
+    }
+
+    /// Allocates `count` layouts of `size` bytes and returns which allocations failed.
+    fn failed(failing: &FailingAlloc<System>, count: usize, size: usize) -> Vec<bool> {
+        (0..count)
+            .map(|_| match failing.alloc(layout(size)) {
+                Ok(ptr) => {
+                    unsafe { failing.dealloc(ptr, layout(size)) };
+                    false
+                }
+                Err(_) => true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failures_parse_and_print() {
+        for spec in &["every:3", "after-bytes:4096", "random:0.25"] {
+            assert_eq!(spec.parse::<Failure>().unwrap().to_string(), *spec);
+        }
+        assert!("every:0".parse::<Failure>().is_err());
+        assert!("random:1.5".parse::<Failure>().is_err());
+        assert!("sometimes:1".parse::<Failure>().is_err());
+        assert!("every".parse::<Failure>().is_err());
+    }
+
+    #[test]
+    fn every_nth_call_fails() {
+        let failing = FailingAlloc::new(System, Failure::Every(3), 0);
+        let expected: Vec<_> = (1..=9)
+            .map(|call| call == 3 || call == 6 || call == 9)
+            .collect();
+        assert_eq!(failed(&failing, 9, 16), expected);
+        assert_eq!(failing.failures(), 3);
+    }
+
+    #[test]
+    fn calls_fail_after_the_byte_limit() {
+        let failing = FailingAlloc::new(System, Failure::AfterBytes(100), 0);
+        assert_eq!(failed(&failing, 3, 40), vec![false, false, true]);
+        // Smaller requests that still fit below the limit succeed.
+        assert_eq!(failed(&failing, 2, 20), vec![false, true]);
+    }
+
+    #[test]
+    fn random_failures_depend_on_the_seed() {
+        let first = failed(&FailingAlloc::new(System, Failure::Random(0.5), 7), 200, 8);
+        let second = failed(&FailingAlloc::new(System, Failure::Random(0.5), 7), 200, 8);
+        assert_eq!(first, second);
+        let count = first.iter().filter(|failed| **failed).count();
+        assert!(count > 50 && count < 150);
+
+        assert!(
+            !failed(&FailingAlloc::new(System, Failure::Random(0.0), 7), 100, 8)[..]
+                .contains(&true)
+        );
+    }
+
+    /// Every mode of the harness copes with failing calls: it neither panics nor leaks.
+    #[test]
+    fn the_harness_handles_failures() {
+        let options = Options {
+            iters: 500,
+            seed: 3,
+            ..Options::default()
+        };
+        for failure in &[
+            Failure::Every(2),
+            Failure::AfterBytes(20_000),
+            Failure::Random(0.3),
+        ] {
+            for &sizes in Sizes::ALL {
+                for &call in Call::ALL {
+                    for &mode in Mode::ALL {
+                        let scenario = Scenario {
+                            sizes,
+                            call,
+                            mode,
+                            ..options.scenario()
+                        };
+                        let workload = Workload::new(&scenario);
+                        let tracking = Tracking::new(System);
+                        let failing = FailingAlloc::new(&tracking, failure.clone(), scenario.seed);
+                        run_test(&failing, &scenario, &workload.batch(0..workload.len()));
+
+                        let stats = tracking.stats();
+                        assert_eq!(stats.live_allocs, 0, "{} leaks with {}", scenario, failure);
+                        if sizes != Sizes::Zero {
+                            assert!(failing.failures() > 0, "{} with {}", scenario, failure);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! A first-fit free-list allocator over a single arena.
+
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::cmp;
+use std::ptr::{self, NonNull};
+
+/// The granularity of blocks; every block is aligned to it and a multiple of it in size, so a
+/// free block always has room for its header.
+const BLOCK_ALIGN: usize = 16;
+
+/// The header of a free block, stored at its start.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Serves allocations from the first free block that fits them. Free blocks are kept in a list
+/// that is sorted by address, so released blocks can be merged with their neighbours.
+pub struct FreeList {
+    arena: NonNull<u8>,
+    capacity: usize,
+    head: Cell<*mut FreeBlock>,
+}
+
+impl FreeList {
+    /// Creates a free list that serves up to `capacity` bytes from an arena allocated up front.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = round_up(cmp::max(capacity, BLOCK_ALIGN), BLOCK_ALIGN);
+        let layout = Layout::from_size_align(capacity, BLOCK_ALIGN).expect("Invalid capacity");
+        let arena = NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+
+        let head = arena.as_ptr() as *mut FreeBlock;
+        unsafe {
+            head.write(FreeBlock {
+                size: capacity,
+                next: ptr::null_mut(),
+            })
+        };
+        FreeList {
+            arena,
+            capacity,
+            head: Cell::new(head),
+        }
+    }
+
+    /// The capacity that a free list needs so that allocating each of `layouts` once never fails,
+    /// however the allocations are interleaved with releases. Every block is rounded up to
+    /// `BLOCK_ALIGN`, and may be preceded by padding up to its alignment.
+    pub fn capacity_for<I: IntoIterator<Item = Layout>>(layouts: I) -> usize {
+        layouts
+            .into_iter()
+            .map(|layout| {
+                let padding = cmp::max(layout.align(), BLOCK_ALIGN) - BLOCK_ALIGN;
+                round_up(layout.size(), BLOCK_ALIGN).saturating_add(padding)
+            })
+            .fold(0, usize::saturating_add)
+    }
+
+    /// The number of bytes in free blocks.
+    #[cfg(test)]
+    fn free_bytes(&self) -> usize {
+        let mut free = 0;
+        let mut block = self.head.get();
+        while !block.is_null() {
+            unsafe {
+                free += (*block).size;
+                block = (*block).next;
+            }
+        }
+        free
+    }
+
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let size = round_up(layout.size(), BLOCK_ALIGN);
+        let align = cmp::max(layout.align(), BLOCK_ALIGN);
+
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut block = self.head.get();
+        while !block.is_null() {
+            unsafe {
+                let start = block as usize;
+                let end = start + (*block).size;
+                // Padding before the allocation is a multiple of `BLOCK_ALIGN`, so it can stay
+                // behind as a free block.
+                let aligned = round_up(start, align);
+                if aligned + size <= end {
+                    let next = (*block).next;
+                    let tail = aligned + size;
+                    let tail_block = if tail < end {
+                        let tail_block = tail as *mut FreeBlock;
+                        tail_block.write(FreeBlock {
+                            size: end - tail,
+                            next,
+                        });
+                        tail_block
+                    } else {
+                        next
+                    };
+
+                    if aligned > start {
+                        (*block).size = aligned - start;
+                        (*block).next = tail_block;
+                    } else {
+                        self.link(prev, tail_block);
+                    }
+                    return Ok(NonNull::new_unchecked(aligned as *mut u8));
+                }
+                prev = block;
+                block = (*block).next;
+            }
+        }
+        Err(AllocErr)
+    }
+
+    /// Returns the block at `ptr` of `size` bytes to the free list, merging it with adjacent
+    /// free blocks.
+    unsafe fn release(&self, ptr: NonNull<u8>, size: usize) {
+        let size = round_up(size, BLOCK_ALIGN);
+        let start = ptr.as_ptr() as usize;
+        debug_assert!(start >= self.arena.as_ptr() as usize);
+        debug_assert!(start + size <= self.arena.as_ptr() as usize + self.capacity);
+
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.head.get();
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if !prev.is_null() && prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            self.link(prev, block);
+        }
+    }
+
+    unsafe fn link(&self, prev: *mut FreeBlock, next: *mut FreeBlock) {
+        if prev.is_null() {
+            self.head.set(next);
+        } else {
+            (*prev).next = next;
+        }
+    }
+
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let old_size = round_up(layout.size(), BLOCK_ALIGN);
+        let new_size = round_up(new_layout.size(), BLOCK_ALIGN);
+        if new_size <= old_size {
+            // Shrink in place by releasing the tail.
+            if new_size < old_size {
+                let tail = NonNull::new_unchecked(ptr.as_ptr().add(new_size));
+                self.release(tail, old_size - new_size);
+            }
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), layout.size());
+        self.release(ptr, layout.size());
+        Ok(new_ptr)
+    }
+}
+
+impl Drop for FreeList {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.capacity, BLOCK_ALIGN).unwrap();
+        unsafe { alloc::dealloc(self.arena.as_ptr(), layout) };
+    }
+}
+
+impl AllocRef for &FreeList {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.allocate(layout.into())
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        let ptr = self.allocate(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        Ok(ptr)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.release(ptr, Layout::from(layout).size())
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.resize(ptr, layout.into(), new_layout.into())
+    }
+}
+
+crate::impl_alloc_ref_v2!(AllocRef for &FreeList);
+
+#[cfg(test)]
+mod tests {
+    use super::FreeList;
+    use crate::conformance::layout;
+    use crate::AllocRefV2;
+    use std::ptr;
+
+    #[test]
+    fn released_blocks_are_merged_and_reused() {
+        let free_list = FreeList::with_capacity(256);
+        let a = free_list.alloc(layout(128, 16)).unwrap();
+        let b = free_list.alloc(layout(128, 16)).unwrap();
+        assert!(free_list.alloc(layout(16, 16)).is_err());
+
+        unsafe {
+            free_list.dealloc(a, layout(128, 16));
+            free_list.dealloc(b, layout(128, 16));
+        }
+        // Only a merged block can serve the whole capacity.
+        let c = free_list.alloc(layout(256, 16)).unwrap();
+        assert_eq!(c, a);
+    }
+
+    #[test]
+    fn exhaustion_returns_an_error() {
+        let free_list = FreeList::with_capacity(1024);
+        assert!(free_list.alloc(layout(2048, 8)).is_err());
+        let ptr = free_list.alloc(layout(1024, 8)).unwrap();
+        assert!(free_list.alloc(layout(1, 1)).is_err());
+        unsafe { free_list.dealloc(ptr, layout(1024, 8)) };
+        assert!(free_list.alloc(layout(1, 1)).is_ok());
+    }
+
+    #[test]
+    fn grow_and_shrink_keep_contents() {
+        let free_list = FreeList::with_capacity(4096);
+        let ptr = free_list.alloc(layout(64, 8)).unwrap();
+        unsafe {
+            ptr::write_bytes(ptr.as_ptr(), 0xAB, 64);
+            let grown = free_list.grow(ptr, layout(64, 8), 512).unwrap();
+            assert!((0..64).all(|i| *grown.as_ptr().add(i) == 0xAB));
+            let shrunk = free_list.shrink(grown, layout(512, 8), 32).unwrap();
+            assert_eq!(shrunk, grown);
+            assert!((0..32).all(|i| *shrunk.as_ptr().add(i) == 0xAB));
+            free_list.dealloc(shrunk, layout(32, 8));
+        }
+        assert_eq!(free_list.free_bytes(), free_list.capacity);
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Number of bits of precision kept per power of two. Every bucket covers at most 1/16th of
+/// the values of its power of two, which bounds the relative error of a percentile to 6.25%.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// The percentiles reported for latency measurements.
+const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+/// A log-bucketed histogram of nanosecond latencies.
+///
+/// Values below 16 are stored exactly; larger values are grouped into 16 linear buckets per
+/// power of two.
+#[derive(Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+    /// Number of operations timed together to produce one recorded value.
+    batch: usize,
+}
+
+impl Histogram {
+    pub fn new(batch: usize) -> Self {
+        Histogram {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            max: 0,
+            batch,
+        }
+    }
+
+    /// Records the duration of a batch of `ops` operations as their average per-operation
+    /// latency.
+    pub fn record_batch(&mut self, elapsed: Duration, ops: usize) {
+        if ops > 0 {
+            self.record((elapsed.as_nanos() / ops as u128) as u64);
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.buckets[bucket_index(value)] += 1;
+        self.count += 1;
+        self.max = self.max.max(value);
+    }
+
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns an upper bound for the `p`-th percentile, with `p` in `[0, 100]`.
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper_bound(index).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "latency:  no samples");
+        }
+
+        writeln!(
+            f,
+            "latency:  {} batches of {} op(s), ns/op",
+            self.count, self.batch
+        )?;
+        for p in PERCENTILES.iter() {
+            writeln!(f, "  p{:<6} {}", p, self.percentile(*p))?;
+        }
+        write!(f, "  {:<7} {}", "max", self.max)
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub_bucket
+}
+
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+    // Computed in 128 bits, because the upper bound of the last bucket is `u64::MAX`.
+    let upper = ((SUB_BUCKETS + sub_bucket + 1) as u128) << shift;
+    (upper - 1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_index, bucket_upper_bound, Histogram, BUCKETS, SUB_BUCKETS};
+
+    /// Values around every power of two, and the extremes.
+    fn boundaries() -> Vec<u64> {
+        let mut values = vec![0, 1, 15, 16, 17, u64::MAX - 1, u64::MAX];
+        for exponent in 1..64 {
+            let power = 1u64 << exponent;
+            values.extend_from_slice(&[power - 1, power, power + 1]);
+        }
+        values.sort();
+        values.dedup();
+        values
+    }
+
+    #[test]
+    fn small_values_are_exact() {
+        for value in 0..2 * SUB_BUCKETS as u64 {
+            assert_eq!(bucket_upper_bound(bucket_index(value)), value);
+        }
+        assert_eq!(bucket_index(16), 16);
+        assert_eq!(bucket_index(17), 17);
+        // From 32 on, a bucket covers more than one value.
+        assert_eq!(bucket_index(32), bucket_index(33));
+        assert_eq!(bucket_upper_bound(bucket_index(32)), 33);
+    }
+
+    #[test]
+    fn buckets_bound_their_values_within_the_relative_error() {
+        let mut previous = 0;
+        for value in boundaries() {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS, "{} has bucket {}", value, index);
+            assert!(index >= previous, "buckets are not monotonic at {}", value);
+            previous = index;
+
+            let upper = bucket_upper_bound(index);
+            assert!(upper >= value, "{} is above its bound {}", value, upper);
+            assert!(
+                upper - value <= value / SUB_BUCKETS as u64,
+                "{} -> {}",
+                value,
+                upper
+            );
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+        assert_eq!(bucket_upper_bound(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn percentiles_are_monotonic_and_bounded_by_the_maximum() {
+        let mut histogram = Histogram::new(1);
+        assert_eq!(histogram.percentile(50.0), 0);
+        for value in boundaries() {
+            histogram.record(value);
+        }
+        for value in 0..1000 {
+            histogram.record(value * 7);
+        }
+
+        assert_eq!(histogram.max(), u64::MAX);
+        let mut previous = 0;
+        for p in 0..=1000 {
+            let percentile = histogram.percentile(p as f64 / 10.0);
+            assert!(
+                percentile >= previous,
+                "p{} is below the previous one",
+                p as f64 / 10.0
+            );
+            previous = percentile;
+        }
+        assert_eq!(histogram.percentile(100.0), u64::MAX);
+        assert_eq!(histogram.percentile(0.0), 0);
+    }
+
+    #[test]
+    fn percentiles_do_not_exceed_the_maximum_of_a_bucket() {
+        let mut histogram = Histogram::new(1);
+        for _ in 0..10 {
+            histogram.record(1000);
+        }
+        // 1000 lies in the bucket 992..=1023, but no larger value was recorded.
+        assert_eq!(histogram.percentile(50.0), 1000);
+        assert_eq!(histogram.percentile(100.0), 1000);
+        assert_eq!(histogram.max(), 1000);
+    }
+}
@@ -0,0 +1,5 @@
+//! The parts of the benchmark that other programs can link against, to capture allocation traces
+//! for `bench-alloc replay`.
+
+pub mod recorder;
+pub mod trace;
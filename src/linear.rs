@@ -0,0 +1,165 @@
+//! A linear allocator over a caller-provided buffer.
+
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr::{self, NonNull};
+
+/// Serves allocations by bumping an offset into a buffer that it borrows, like a static or a
+/// stack buffer, so it never calls the system allocator. Releasing or resizing the most recent
+/// allocation moves the offset back like a stack; any other memory is only reclaimed by
+/// `reset`.
+pub struct Linear<'a> {
+    start: *mut u8,
+    capacity: usize,
+    offset: Cell<usize>,
+    buffer: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> Linear<'a> {
+    pub fn new(buffer: &'a mut [MaybeUninit<u8>]) -> Self {
+        Linear {
+            start: buffer.as_mut_ptr() as *mut u8,
+            capacity: buffer.len(),
+            offset: Cell::new(0),
+            buffer: PhantomData,
+        }
+    }
+
+    /// Releases all allocations at once.
+    pub fn reset(&mut self) {
+        self.offset.set(0);
+    }
+
+    /// The number of bytes in use, including padding for alignment.
+    #[cfg(test)]
+    fn used(&self) -> usize {
+        self.offset.get()
+    }
+
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let start = self.start as usize;
+        let top = start + self.offset.get();
+        let aligned = top.checked_add(layout.align() - 1).ok_or(AllocErr)? & !(layout.align() - 1);
+        let end = aligned.checked_add(layout.size()).ok_or(AllocErr)?;
+        if end > start + self.capacity {
+            return Err(AllocErr);
+        }
+        self.offset.set(end - start);
+        Ok(unsafe { NonNull::new_unchecked(aligned as *mut u8) })
+    }
+
+    /// Whether `size` bytes at `ptr` are the most recent allocation.
+    fn is_top(&self, ptr: NonNull<u8>, size: usize) -> bool {
+        ptr.as_ptr() as usize + size == self.start as usize + self.offset.get()
+    }
+
+    fn release(&self, ptr: NonNull<u8>, size: usize) {
+        if self.is_top(ptr, size) {
+            self.offset.set(ptr.as_ptr() as usize - self.start as usize);
+        }
+    }
+
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let aligned = ptr.as_ptr() as usize & (new_layout.align() - 1) == 0;
+        if aligned && self.is_top(ptr, layout.size()) {
+            // The most recent allocation can be resized in place, up to the end of the buffer.
+            let offset = ptr.as_ptr() as usize - self.start as usize;
+            if new_layout.size() > self.capacity - offset {
+                return Err(AllocErr);
+            }
+            self.offset.set(offset + new_layout.size());
+            return Ok(ptr);
+        }
+        if aligned && new_layout.size() <= layout.size() {
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        let size = cmp::min(layout.size(), new_layout.size());
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), size);
+        Ok(new_ptr)
+    }
+}
+
+impl AllocRef for &Linear<'_> {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.allocate(layout.into())
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        let ptr = self.allocate(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        Ok(ptr)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.release(ptr, Layout::from(layout).size())
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.resize(ptr, layout.into(), new_layout.into())
+    }
+}
+
+crate::impl_alloc_ref_v2!(AllocRef for &Linear<'_>);
+
+#[cfg(test)]
+mod tests {
+    use super::Linear;
+    use crate::conformance::layout;
+    use crate::AllocRefV2;
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    #[test]
+    fn exhaustion_returns_an_error_until_reset() {
+        let mut buffer = [MaybeUninit::uninit(); 256];
+        let mut linear = Linear::new(&mut buffer);
+        assert!(linear.alloc(layout(257, 1)).is_err());
+        linear.alloc(layout(200, 8)).unwrap();
+        assert!(linear.alloc(layout(64, 8)).is_err());
+        assert!(linear.alloc(layout(isize::MAX as usize, 1)).is_err());
+
+        linear.reset();
+        assert_eq!(linear.used(), 0);
+        assert!(linear.alloc(layout(256, 1)).is_ok());
+    }
+
+    #[test]
+    fn the_most_recent_allocation_is_released_and_resized_in_place() {
+        let mut buffer = [MaybeUninit::uninit(); 1024];
+        let linear = Linear::new(&mut buffer);
+        let a = linear.alloc(layout(32, 8)).unwrap();
+        let b = linear.alloc(layout(32, 8)).unwrap();
+        unsafe {
+            ptr::write_bytes(a.as_ptr(), 0xEF, 32);
+            let grown = linear.grow(b, layout(32, 8), 512).unwrap();
+            assert_eq!(grown, b);
+            linear.dealloc(grown, layout(512, 8));
+            assert_eq!(linear.used(), b.as_ptr() as usize - linear.start as usize);
+
+            // `a` is no longer the most recent allocation once `c` exists, so it is copied.
+            let c = linear.alloc(layout(16, 8)).unwrap();
+            let moved = linear.grow(a, layout(32, 8), 64).unwrap();
+            assert_ne!(moved, a);
+            assert!((0..32).all(|i| *moved.as_ptr().add(i) == 0xEF));
+            assert!(linear.grow(moved, layout(64, 8), 1024).is_err());
+            linear.dealloc(c, layout(16, 8));
+        }
+    }
+}
@@ -1,15 +1,72 @@
-use rand::{thread_rng, Rng};
-use std::alloc::Layout;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::alloc::{Layout, System};
 use std::convert::TryInto;
 use std::env;
+use std::io;
 use std::iter::Iterator;
-use std::ptr::NonNull;
-use std::time::Instant;
+use std::mem::MaybeUninit;
+use std::ops::{Add, Range};
+use std::process;
+use std::ptr::{self, NonNull};
+use std::time::Duration;
 
 use alloc_wg::alloc::{AllocErr, AllocRef, Global, NonZeroLayout};
+use bench_alloc::trace::{Event, Operation, TraceReader, TraceWriter};
 use bumpalo::Bump;
 
-trait AllocRefV2: Sized {
+use arena::ChunkStats;
+use cli::{Analysis, Command, Format, Options};
+use clock::Clock;
+use counters::{Counters, Tally};
+use failing::FailingAlloc;
+use free_list::FreeList;
+use histogram::Histogram;
+use linear::Linear;
+use malloc::{AlignedAlloc, Malloc, PosixMemalign};
+use output::{Environment, Record};
+use padding::PaddingAnalysis;
+use pool::Pool;
+use replay::Replay;
+use scenario::{Allocator, Call, Choice, Mode, Pattern, Scenario, Sizes};
+use slab::Slab;
+use stats::{black_box, MeasureConfig, Summary};
+use tracking::{Tracking, TrackingStats};
+use verify::{Verification, Verifying};
+
+mod arena;
+mod cli;
+mod clock;
+#[cfg(test)]
+mod conformance;
+mod counters;
+mod distribution;
+mod failing;
+mod free_list;
+mod histogram;
+mod linear;
+mod malloc;
+mod matrix;
+mod output;
+mod padding;
+mod pool;
+#[cfg(test)]
+mod property;
+mod replay;
+mod scenario;
+mod slab;
+mod stats;
+mod tracking;
+mod verify;
+
+/// Converts a layout that was checked to be non-zero-sized, propagating an error instead of
+/// panicking if it is not.
+#[inline(always)]
+fn non_zero(layout: Layout) -> Result<NonZeroLayout, AllocErr> {
+    layout.try_into().map_err(|_| AllocErr)
+}
+
+trait AllocRefV2: Sized + Copy {
     fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr>;
 
     #[inline(always)]
@@ -30,7 +87,115 @@ trait AllocRefV2: Sized {
         if layout.size() == 0 {
             self.alloc_zst(layout)
         } else {
-            self.alloc_non_zst(layout.try_into().unwrap())
+            self.alloc_non_zst(non_zero(layout)?)
+        }
+    }
+
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr>;
+
+    #[inline(always)]
+    fn alloc_zeroed_zst(self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        // A zero-sized allocation has no bytes to zero.
+        self.alloc_zst(layout)
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed(self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        if layout.size() == 0 {
+            self.alloc_zeroed_zst(layout)
+        } else {
+            self.alloc_zeroed_non_zst(non_zero(layout)?)
+        }
+    }
+
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, layout: NonZeroLayout);
+
+    #[inline(always)]
+    unsafe fn dealloc_zst(self, _ptr: NonNull<u8>, _layout: Layout) {
+        // Pointers to zero-sized allocations are dangling, so there is nothing to release.
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            self.dealloc_zst(ptr, layout)
+        } else if let Ok(layout) = non_zero(layout) {
+            self.dealloc_non_zst(ptr, layout)
+        }
+    }
+
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr>;
+
+    /// Grows a zero-sized allocation, which owns no memory, into a non-zero-sized one.
+    #[inline(always)]
+    unsafe fn grow_zst(
+        self,
+        _ptr: NonNull<u8>,
+        _layout: Layout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.alloc_non_zst(new_layout)
+    }
+
+    /// Grows the allocation at `ptr` to `new_size` bytes, keeping its alignment.
+    ///
+    /// `new_size` must be greater than `layout.size()`.
+    #[inline(always)]
+    unsafe fn grow(
+        self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        debug_assert!(new_size > layout.size());
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if layout.size() == 0 {
+            self.grow_zst(ptr, layout, non_zero(new_layout)?)
+        } else {
+            self.grow_non_zst(ptr, non_zero(layout)?, non_zero(new_layout)?)
+        }
+    }
+
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr>;
+
+    /// Shrinks a non-zero-sized allocation into a zero-sized one, releasing its memory.
+    #[inline(always)]
+    unsafe fn shrink_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.dealloc_non_zst(ptr, layout);
+        self.alloc_zst(new_layout)
+    }
+
+    /// Shrinks the allocation at `ptr` to `new_size` bytes, keeping its alignment.
+    ///
+    /// `new_size` must be smaller than `layout.size()`.
+    #[inline(always)]
+    unsafe fn shrink(
+        self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        debug_assert!(new_size < layout.size());
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if new_size == 0 {
+            self.shrink_zst(ptr, non_zero(layout)?, new_layout)
+        } else {
+            self.shrink_non_zst(ptr, non_zero(layout)?, non_zero(new_layout)?)
         }
     }
 }
@@ -40,115 +205,1604 @@ impl<A: AllocRef> AllocRefV2 for &Bump<A> {
     fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
         AllocRef::alloc(self, layout.into())
     }
-}
 
-impl AllocRefV2 for Global {
     #[inline(always)]
-    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
-        AllocRef::alloc(self, layout)
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        AllocRef::alloc_zeroed(self, layout.into())
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        AllocRef::dealloc(self, ptr, layout.into())
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        AllocRef::realloc(self, ptr, layout.into(), new_layout.into())
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        AllocRef::realloc(self, ptr, layout.into(), new_layout.into())
     }
 }
 
-fn make_layouts(num: usize, is_zero: bool) -> Vec<Layout> {
-    let mut rng = thread_rng();
-    (0..num)
-        .map(|_| {
+/// Implements `AllocRefV2` for `$ty` by forwarding the calls on non-zero-sized layouts to its
+/// `AllocRef` or `GlobalAlloc` implementation. Zero-sized layouts keep the defaults of the trait.
+macro_rules! impl_alloc_ref_v2 {
+    (AllocRef for $ty:ty) => {
+        impl $crate::AllocRefV2 for $ty {
+            #[inline(always)]
+            fn alloc_non_zst(
+                self,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                alloc_wg::alloc::AllocRef::alloc(self, layout)
+            }
+
+            #[inline(always)]
+            fn alloc_zeroed_non_zst(
+                self,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                alloc_wg::alloc::AllocRef::alloc_zeroed(self, layout)
+            }
+
+            #[inline(always)]
+            unsafe fn dealloc_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) {
+                alloc_wg::alloc::AllocRef::dealloc(self, ptr, layout)
+            }
+
+            #[inline(always)]
+            unsafe fn grow_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+                new_layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                alloc_wg::alloc::AllocRef::realloc(self, ptr, layout, new_layout)
+            }
+
+            #[inline(always)]
+            unsafe fn shrink_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+                new_layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                alloc_wg::alloc::AllocRef::realloc(self, ptr, layout, new_layout)
+            }
+        }
+    };
+    (GlobalAlloc for $ty:ty) => {
+        impl $crate::AllocRefV2 for $ty {
+            #[inline(always)]
+            fn alloc_non_zst(
+                self,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                let ptr = unsafe { std::alloc::GlobalAlloc::alloc(&self, layout.into()) };
+                std::ptr::NonNull::new(ptr).ok_or(alloc_wg::alloc::AllocErr)
+            }
+
+            #[inline(always)]
+            fn alloc_zeroed_non_zst(
+                self,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                let ptr = unsafe { std::alloc::GlobalAlloc::alloc_zeroed(&self, layout.into()) };
+                std::ptr::NonNull::new(ptr).ok_or(alloc_wg::alloc::AllocErr)
+            }
+
+            #[inline(always)]
+            unsafe fn dealloc_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+            ) {
+                std::alloc::GlobalAlloc::dealloc(&self, ptr.as_ptr(), layout.into())
+            }
+
+            #[inline(always)]
+            unsafe fn grow_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+                new_layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                let new_size = std::alloc::Layout::from(new_layout).size();
+                let ptr =
+                    std::alloc::GlobalAlloc::realloc(&self, ptr.as_ptr(), layout.into(), new_size);
+                std::ptr::NonNull::new(ptr).ok_or(alloc_wg::alloc::AllocErr)
+            }
+
+            #[inline(always)]
+            unsafe fn shrink_non_zst(
+                self,
+                ptr: std::ptr::NonNull<u8>,
+                layout: alloc_wg::alloc::NonZeroLayout,
+                new_layout: alloc_wg::alloc::NonZeroLayout,
+            ) -> Result<std::ptr::NonNull<u8>, alloc_wg::alloc::AllocErr> {
+                <$ty as $crate::AllocRefV2>::grow_non_zst(self, ptr, layout, new_layout)
+            }
+        }
+    };
+}
+use impl_alloc_ref_v2;
+
+impl_alloc_ref_v2!(AllocRef for Global);
+impl_alloc_ref_v2!(GlobalAlloc for System);
+
+/// Decides which of the scenario's layouts are zero-sized.
+fn make_zero_flags<R: Rng>(scenario: &Scenario, rng: &mut R) -> Vec<bool> {
+    let num = scenario.iters;
+    let ratio = scenario.zst_ratio;
+    match scenario.sizes {
+        Sizes::Zero => vec![true; num],
+        Sizes::NonZero => vec![false; num],
+        Sizes::Mixed => match scenario.pattern {
+            Pattern::Random => (0..num).map(|_| rng.gen_bool(ratio)).collect(),
+            // Zero-sized layouts are spread as evenly as the ratio allows.
+            Pattern::Periodic => (0..num)
+                .map(|i| ((i + 1) as f64 * ratio).floor() > (i as f64 * ratio).floor())
+                .collect(),
+            Pattern::Bursty => {
+                let rarer = ratio.min(1.0 - ratio);
+                if rarer <= 0.0 {
+                    return vec![ratio >= 1.0; num];
+                }
+                // Runs have geometrically distributed lengths. Runs of the rarer kind average
+                // `burst` layouts and runs of the other kind are scaled to keep the ratio.
+                let burst = scenario.burst as f64;
+                let mean_zero = burst * ratio / rarer;
+                let mean_non_zero = burst * (1.0 - ratio) / rarer;
+                let mut is_zero = rng.gen_bool(ratio);
+                (0..num)
+                    .map(|_| {
+                        let current = is_zero;
+                        let mean = if current { mean_zero } else { mean_non_zero };
+                        if rng.gen_bool(1.0 / mean) {
+                            is_zero = !is_zero;
+                        }
+                        current
+                    })
+                    .collect()
+            }
+        },
+    }
+}
+
+fn make_layouts<R: Rng>(scenario: &Scenario, rng: &mut R) -> Vec<Layout> {
+    make_zero_flags(scenario, rng)
+        .into_iter()
+        .map(|is_zero| {
             let size: usize = if is_zero {
-                rng.gen_range(0, 1)
+                0
             } else {
-                rng.gen_range(1, 1025)
+                scenario.size_dist.sample(rng)
             };
-            let align: usize = 2usize.pow(rng.gen_range(0, 4));
+            let align: usize = scenario.align_dist.sample(rng);
+            // Distributions only produce sizes up to `MAX_SIZE` and powers of two as alignments.
             Layout::from_size_align(size, align).expect("Failed to create layout")
         })
         .collect()
 }
 
-fn test_alloc<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) {
+/// Returns a copy of `layouts` with every size increased by a random, non-zero amount.
+fn make_grown_layouts<R: Rng>(layouts: &[Layout], rng: &mut R) -> Vec<Layout> {
+    layouts
+        .iter()
+        .map(|layout| {
+            let size = layout.size() + rng.gen_range(1, distribution::MAX_GROWTH + 1);
+            // Sizes are at most `MAX_SIZE`, which leaves room to grow them.
+            Layout::from_size_align(size, layout.align()).expect("Failed to create layout")
+        })
+        .collect()
+}
+
+/// The number of calls that failed among `results`.
+fn failures(results: &[Result<NonNull<u8>, AllocErr>]) -> usize {
+    results.iter().filter(|result| result.is_err()).count()
+}
+
+fn release<A: AllocRefV2 + Copy, L: Copy + Into<Layout>>(
+    a: A,
+    allocations: &[Result<NonNull<u8>, AllocErr>],
+    layouts: &[L],
+) {
+    for (allocation, layout) in allocations.iter().zip(layouts) {
+        if let Ok(ptr) = allocation {
+            unsafe { a.dealloc(*ptr, (*layout).into()) };
+        }
+    }
+}
+
+/// Allocates `layouts` for resizing them to `new_layouts`. Layouts that fail to allocate are
+/// left out.
+fn allocate_resizable<L: Copy, N: Copy, F: Fn(L) -> Result<NonNull<u8>, AllocErr>>(
+    layouts: &[L],
+    new_layouts: &[N],
+    alloc: F,
+) -> Vec<(NonNull<u8>, L, N)> {
+    layouts
+        .iter()
+        .zip(new_layouts)
+        .filter_map(|(layout, new_layout)| Some((alloc(*layout).ok()?, *layout, *new_layout)))
+        .collect()
+}
+
+/// Releases resized allocations, or the original ones where resizing them failed.
+fn release_resized<A: AllocRefV2 + Copy, L: Copy + Into<Layout>, N: Copy + Into<Layout>>(
+    a: A,
+    allocations: &[(NonNull<u8>, L, N)],
+    reallocations: &[Result<NonNull<u8>, AllocErr>],
+) {
+    for ((ptr, layout, new_layout), reallocation) in allocations.iter().zip(reallocations) {
+        match reallocation {
+            Ok(new_ptr) => unsafe { a.dealloc(*new_ptr, (*new_layout).into()) },
+            Err(_) => unsafe { a.dealloc(*ptr, (*layout).into()) },
+        }
+    }
+}
+
+fn test_alloc<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc_zst(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) -> TestRun {
     let mut allocations = Vec::with_capacity(layouts.len());
 
-    let before = Instant::now();
+    let before = clock::now();
     for layout in layouts {
-        allocations.push(a.alloc(*layout));
+        allocations.push(black_box(a.alloc_non_zst(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_zeroed<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc_zeroed(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
     }
-    println!("{}", before.elapsed().as_micros());
 }
 
-fn test_alloc_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) {
+fn test_alloc_zeroed_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
     let mut allocations = Vec::with_capacity(layouts.len());
 
-    let before = Instant::now();
+    let before = clock::now();
     for layout in layouts {
-        allocations.push(a.alloc_zst(*layout));
+        allocations.push(black_box(a.alloc_zeroed_zst(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
     }
-    println!("{}", before.elapsed().as_micros());
 }
 
-fn test_alloc_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) {
+fn test_alloc_zeroed_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) -> TestRun {
     let mut allocations = Vec::with_capacity(layouts.len());
 
-    let before = Instant::now();
+    let before = clock::now();
     for layout in layouts {
-        allocations.push(a.alloc_non_zst(*layout));
+        allocations.push(black_box(a.alloc_zeroed_non_zst(*layout)));
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_memset<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        let allocation = black_box(a.alloc(*layout));
+        if let Ok(ptr) = allocation {
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        }
+        allocations.push(allocation);
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_memset_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        let allocation = black_box(a.alloc_zst(*layout));
+        if let Ok(ptr) = allocation {
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        }
+        allocations.push(allocation);
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_memset_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        let allocation = black_box(a.alloc_non_zst(*layout));
+        if let Ok(ptr) = allocation {
+            let size = Layout::from(*layout).size();
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0, size) };
+        }
+        allocations.push(allocation);
+    }
+    let elapsed = before.elapsed();
+
+    release(a, &allocations, layouts);
+
+    TestRun {
+        elapsed,
+        failures: failures(&allocations),
     }
-    println!("{}", before.elapsed().as_micros());
 }
 
-fn run_test<A: AllocRefV2 + Copy>(a: A, iters: usize, is_direct: bool, is_zero: bool) {
-    let layouts = make_layouts(iters, is_zero);
-    if is_direct {
-        if is_zero {
-            test_alloc_zst(a, &layouts);
+fn test_dealloc<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    // Layouts that fail to allocate are left out.
+    let allocations: Vec<_> = layouts
+        .iter()
+        .filter_map(|layout| Some((a.alloc(*layout).ok()?, *layout)))
+        .collect();
+
+    let before = clock::now();
+    for (ptr, layout) in &allocations {
+        unsafe { a.dealloc(*ptr, *layout) };
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: layouts.len() - allocations.len(),
+    }
+}
+
+fn test_dealloc_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    // Layouts that fail to allocate are left out.
+    let allocations: Vec<_> = layouts
+        .iter()
+        .filter_map(|layout| Some((a.alloc_zst(*layout).ok()?, *layout)))
+        .collect();
+
+    let before = clock::now();
+    for (ptr, layout) in &allocations {
+        unsafe { a.dealloc_zst(*ptr, *layout) };
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: layouts.len() - allocations.len(),
+    }
+}
+
+fn test_dealloc_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) -> TestRun {
+    // Layouts that fail to allocate are left out.
+    let allocations: Vec<_> = layouts
+        .iter()
+        .filter_map(|layout| Some((a.alloc_non_zst(*layout).ok()?, *layout)))
+        .collect();
+
+    let before = clock::now();
+    for (ptr, layout) in &allocations {
+        unsafe { a.dealloc_non_zst(*ptr, *layout) };
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: layouts.len() - allocations.len(),
+    }
+}
+
+fn test_alloc_dealloc<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc(*layout)));
+    }
+    for (allocation, layout) in allocations.iter().zip(layouts) {
+        if let Ok(ptr) = allocation {
+            unsafe { a.dealloc(*ptr, *layout) };
+        }
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_dealloc_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc_zst(*layout)));
+    }
+    for (allocation, layout) in allocations.iter().zip(layouts) {
+        if let Ok(ptr) = allocation {
+            unsafe { a.dealloc_zst(*ptr, *layout) };
+        }
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: failures(&allocations),
+    }
+}
+
+fn test_alloc_dealloc_non_zst<A: AllocRefV2 + Copy>(a: A, layouts: &[NonZeroLayout]) -> TestRun {
+    let mut allocations = Vec::with_capacity(layouts.len());
+
+    let before = clock::now();
+    for layout in layouts {
+        allocations.push(black_box(a.alloc_non_zst(*layout)));
+    }
+    for (allocation, layout) in allocations.iter().zip(layouts) {
+        if let Ok(ptr) = allocation {
+            unsafe { a.dealloc_non_zst(*ptr, *layout) };
+        }
+    }
+    TestRun {
+        elapsed: before.elapsed(),
+        failures: failures(&allocations),
+    }
+}
+
+fn test_grow<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout], new_layouts: &[Layout]) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe {
+            a.grow(*ptr, *layout, new_layout.size())
+        }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+fn test_grow_zst<A: AllocRefV2 + Copy>(
+    a: A,
+    layouts: &[Layout],
+    new_layouts: &[NonZeroLayout],
+) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc_zst(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe { a.grow_zst(*ptr, *layout, *new_layout) }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+fn test_grow_non_zst<A: AllocRefV2 + Copy>(
+    a: A,
+    layouts: &[NonZeroLayout],
+    new_layouts: &[NonZeroLayout],
+) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc_non_zst(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe {
+            a.grow_non_zst(*ptr, *layout, *new_layout)
+        }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+fn test_shrink<A: AllocRefV2 + Copy>(a: A, layouts: &[Layout], new_layouts: &[Layout]) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe {
+            a.shrink(*ptr, *layout, new_layout.size())
+        }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+fn test_shrink_zst<A: AllocRefV2 + Copy>(
+    a: A,
+    layouts: &[NonZeroLayout],
+    new_layouts: &[Layout],
+) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc_non_zst(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe {
+            a.shrink_zst(*ptr, *layout, *new_layout)
+        }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+fn test_shrink_non_zst<A: AllocRefV2 + Copy>(
+    a: A,
+    layouts: &[NonZeroLayout],
+    new_layouts: &[NonZeroLayout],
+) -> TestRun {
+    let allocations = allocate_resizable(layouts, new_layouts, |layout| a.alloc_non_zst(layout));
+    let mut reallocations = Vec::with_capacity(allocations.len());
+
+    let before = clock::now();
+    for (ptr, layout, new_layout) in &allocations {
+        reallocations.push(black_box(unsafe {
+            a.shrink_non_zst(*ptr, *layout, *new_layout)
+        }));
+    }
+    let elapsed = before.elapsed();
+
+    release_resized(a, &allocations, &reallocations);
+
+    TestRun {
+        elapsed,
+        failures: layouts.len() - allocations.len() + failures(&reallocations),
+    }
+}
+
+/// The loops that the test of `mode` runs over its layouts, without calling the allocator:
+/// allocating loops load every layout and store a result for it, releasing loops load every
+/// allocation back. Subtracting the duration leaves the cost of the calls themselves.
+fn test_baseline<L: Copy + Into<Layout>>(mode: Mode, layouts: &[L]) -> Duration {
+    let dangling = |layout: L| -> Result<NonNull<u8>, AllocErr> {
+        let layout: Layout = layout.into();
+        Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) })
+    };
+    let allocates = mode != Mode::Dealloc;
+    let releases = mode == Mode::Dealloc || mode == Mode::AllocDealloc;
+
+    let mut allocations = Vec::with_capacity(layouts.len());
+    if !allocates {
+        allocations.extend(layouts.iter().map(|layout| dangling(*layout)));
+    }
+
+    let before = clock::now();
+    if allocates {
+        for layout in layouts {
+            allocations.push(black_box(dangling(*layout)));
+        }
+    }
+    if releases {
+        for (allocation, layout) in allocations.iter().zip(layouts) {
+            if let Ok(ptr) = allocation {
+                black_box((*ptr, *layout));
+            }
+        }
+    }
+    let elapsed = before.elapsed();
+
+    black_box(allocations);
+
+    elapsed
+}
+
+/// The layouts a scenario runs on. They are generated once, so every sample of a measurement
+/// works on the same sequence.
+struct Workload {
+    layouts: Vec<Layout>,
+    /// `layouts` grown by a random amount; only generated for the grow and shrink modes.
+    grown_layouts: Vec<Layout>,
+    /// For direct calls, the zero-sized and non-zero-sized layouts are split off, so each kind
+    /// can be dispatched to its own method without branching, as if by an oracle.
+    zero_layouts: Vec<Layout>,
+    grown_zero_layouts: Vec<NonZeroLayout>,
+    non_zero_layouts: Vec<NonZeroLayout>,
+    grown_non_zero_layouts: Vec<NonZeroLayout>,
+    /// `zero_counts[i]` is the number of zero-sized layouts among the first `i` layouts, which
+    /// locates a batch in the split layouts. Only generated for direct calls.
+    zero_counts: Vec<usize>,
+}
+
+impl Workload {
+    fn new(scenario: &Scenario) -> Self {
+        // The layouts only depend on the seed, so a run can be replayed exactly.
+        let mut rng = ChaCha8Rng::seed_from_u64(scenario.seed);
+        let layouts = make_layouts(scenario, &mut rng);
+        let grown_layouts = match scenario.mode {
+            Mode::Grow | Mode::Shrink => make_grown_layouts(&layouts, &mut rng),
+            _ => Vec::new(),
+        };
+
+        let mut zero_layouts = Vec::new();
+        let mut grown_zero_layouts = Vec::new();
+        let mut non_zero_layouts = Vec::new();
+        let mut grown_non_zero_layouts = Vec::new();
+        let mut zero_counts = Vec::new();
+        if scenario.is_direct() {
+            zero_counts.push(0);
+            for (index, layout) in layouts.iter().enumerate() {
+                let grown: Option<NonZeroLayout> = grown_layouts
+                    .get(index)
+                    .map(|grown| (*grown).try_into().unwrap());
+                if layout.size() == 0 {
+                    zero_layouts.push(*layout);
+                    grown_zero_layouts.extend(grown);
+                } else {
+                    non_zero_layouts.push((*layout).try_into().unwrap());
+                    grown_non_zero_layouts.extend(grown);
+                }
+                zero_counts.push(zero_layouts.len());
+            }
+        }
+
+        Workload {
+            layouts,
+            grown_layouts,
+            zero_layouts,
+            grown_zero_layouts,
+            non_zero_layouts,
+            grown_non_zero_layouts,
+            zero_counts,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    /// Every layout that the workload allocates, including the ones it grows or shrinks to.
+    fn all_layouts(&self) -> impl Iterator<Item = Layout> + Clone + '_ {
+        self.layouts.iter().chain(&self.grown_layouts).copied()
+    }
+
+    /// An upper bound of the memory that all layouts need at the same time, including padding
+    /// for their alignment.
+    fn footprint(&self) -> usize {
+        self.all_layouts()
+            .map(|layout| layout.size() + layout.align())
+            .fold(0, usize::saturating_add)
+    }
+
+    /// The smallest layout that fits every layout of the workload.
+    fn max_layout(&self) -> Layout {
+        let layouts = self.all_layouts();
+        let size = layouts
+            .clone()
+            .map(|layout| layout.size())
+            .max()
+            .unwrap_or(0);
+        let align = layouts.map(|layout| layout.align()).max().unwrap_or(1);
+        Layout::from_size_align(size, align).expect("Failed to create layout")
+    }
+
+    /// Checks that the arena which `allocator` preallocates for the workload fits into the
+    /// address space.
+    fn check_arena(&self, allocator: Allocator) -> Result<(), String> {
+        let needed = match allocator {
+            Allocator::BumpUndersized | Allocator::Linear => Some(self.footprint()),
+            Allocator::FreeList => Some(FreeList::capacity_for(self.all_layouts())),
+            Allocator::Pool => Pool::footprint(self.max_layout(), self.len()),
+            _ => return Ok(()),
+        };
+        match needed {
+            Some(needed) if needed <= isize::MAX as usize => Ok(()),
+            _ => Err(format!(
+                "The arena of '{}' cannot hold every layout of the workload; use smaller sizes \
+                 or fewer iterations.",
+                allocator.name()
+            )),
+        }
+    }
+
+    /// Returns the operations in `range`.
+    fn batch(&self, range: Range<usize>) -> Batch<'_> {
+        fn slice<T>(layouts: &[T], range: Range<usize>) -> &[T] {
+            if layouts.is_empty() {
+                layouts
+            } else {
+                &layouts[range]
+            }
+        }
+
+        let (zero_range, non_zero_range) = if self.zero_counts.is_empty() {
+            (0..0, 0..0)
         } else {
-            test_alloc_non_zst(
-                a,
-                &layouts
-                    .iter()
-                    .map(|l| (*l).try_into().unwrap())
-                    .collect::<Vec<_>>(),
+            let zero_start = self.zero_counts[range.start];
+            let zero_end = self.zero_counts[range.end];
+            (
+                zero_start..zero_end,
+                range.start - zero_start..range.end - zero_end,
             )
+        };
+
+        Batch {
+            layouts: slice(&self.layouts, range.clone()),
+            grown_layouts: slice(&self.grown_layouts, range),
+            zero_layouts: slice(&self.zero_layouts, zero_range.clone()),
+            grown_zero_layouts: slice(&self.grown_zero_layouts, zero_range),
+            non_zero_layouts: slice(&self.non_zero_layouts, non_zero_range.clone()),
+            grown_non_zero_layouts: slice(&self.grown_non_zero_layouts, non_zero_range),
+        }
+    }
+}
+
+/// A contiguous part of a `Workload`.
+struct Batch<'a> {
+    layouts: &'a [Layout],
+    grown_layouts: &'a [Layout],
+    zero_layouts: &'a [Layout],
+    grown_zero_layouts: &'a [NonZeroLayout],
+    non_zero_layouts: &'a [NonZeroLayout],
+    grown_non_zero_layouts: &'a [NonZeroLayout],
+}
+
+/// The result of running the operations of a batch once.
+#[derive(Clone, Copy, Default)]
+struct TestRun {
+    elapsed: Duration,
+    /// The number of calls that failed, including the allocations that resizing and releasing
+    /// tests make up front.
+    failures: usize,
+}
+
+impl Add for TestRun {
+    type Output = TestRun;
+
+    fn add(self, other: TestRun) -> TestRun {
+        TestRun {
+            elapsed: self.elapsed + other.elapsed,
+            failures: self.failures + other.failures,
         }
+    }
+}
+
+fn run_test<A: AllocRefV2 + Copy>(a: A, scenario: &Scenario, batch: &Batch) -> TestRun {
+    match &scenario.failure {
+        Some(failure) => {
+            let failing = FailingAlloc::new(a, failure.clone(), scenario.seed);
+            run_operations(&failing, scenario, batch)
+        }
+        None => run_operations(a, scenario, batch),
+    }
+}
+
+fn run_operations<A: AllocRefV2 + Copy>(a: A, scenario: &Scenario, batch: &Batch) -> TestRun {
+    if scenario.is_direct() {
+        // Each kind of layout runs in its own loop, so no call has to branch on the size.
+        run_test_zst(a, scenario.mode, batch) + run_test_non_zst(a, scenario.mode, batch)
     } else {
-        test_alloc(a, &layouts);
+        let layouts = batch.layouts;
+        let grown = batch.grown_layouts;
+        match scenario.mode {
+            Mode::Alloc => test_alloc(a, layouts),
+            Mode::AllocZeroed => test_alloc_zeroed(a, layouts),
+            Mode::AllocMemset => test_alloc_memset(a, layouts),
+            Mode::Dealloc => test_dealloc(a, layouts),
+            Mode::AllocDealloc => test_alloc_dealloc(a, layouts),
+            Mode::Grow => test_grow(a, layouts, grown),
+            Mode::Shrink => test_shrink(a, grown, layouts),
+        }
     }
 }
 
-/// To run the test, provide the command-line arguments for:
-/// - the number of iterations
-/// - type of allocator (false: global, true: bump)
-/// - type of allocation-size distribution (false: randomly distributed, non-zero, true: zero-sized)
-/// - type of function calls (false: branched, true: direct)
+/// Runs the baseline loop over the layouts of `batch`, split like `run_operations` splits them.
+fn run_baseline(scenario: &Scenario, batch: &Batch) -> Duration {
+    if scenario.is_direct() {
+        test_baseline(scenario.mode, batch.zero_layouts)
+            + test_baseline(scenario.mode, batch.non_zero_layouts)
+    } else {
+        test_baseline(scenario.mode, batch.layouts)
+    }
+}
+
+fn run_test_zst<A: AllocRefV2 + Copy>(a: A, mode: Mode, batch: &Batch) -> TestRun {
+    let layouts = batch.zero_layouts;
+    let grown = batch.grown_zero_layouts;
+    if layouts.is_empty() {
+        return TestRun::default();
+    }
+    match mode {
+        Mode::Alloc => test_alloc_zst(a, layouts),
+        Mode::AllocZeroed => test_alloc_zeroed_zst(a, layouts),
+        Mode::AllocMemset => test_alloc_memset_zst(a, layouts),
+        Mode::Dealloc => test_dealloc_zst(a, layouts),
+        Mode::AllocDealloc => test_alloc_dealloc_zst(a, layouts),
+        Mode::Grow => test_grow_zst(a, layouts, grown),
+        Mode::Shrink => test_shrink_zst(a, grown, layouts),
+    }
+}
+
+fn run_test_non_zst<A: AllocRefV2 + Copy>(a: A, mode: Mode, batch: &Batch) -> TestRun {
+    let layouts = batch.non_zero_layouts;
+    let grown = batch.grown_non_zero_layouts;
+    if layouts.is_empty() {
+        return TestRun::default();
+    }
+    match mode {
+        Mode::Alloc => test_alloc_non_zst(a, layouts),
+        Mode::AllocZeroed => test_alloc_zeroed_non_zst(a, layouts),
+        Mode::AllocMemset => test_alloc_memset_non_zst(a, layouts),
+        Mode::Dealloc => test_dealloc_non_zst(a, layouts),
+        Mode::AllocDealloc => test_alloc_dealloc_non_zst(a, layouts),
+        Mode::Grow => test_grow_non_zst(a, layouts, grown),
+        Mode::Shrink => test_shrink_non_zst(a, grown, layouts),
+    }
+}
+
+/// Runs the workload in batches of `batch_size` operations and records the per-operation latency
+/// of every batch. Each batch releases its memory before the next one starts, so the allocator
+/// sees a smaller live set than in a regular run. Injected failures continue across the batches,
+/// like they do in a regular run.
+fn record_latencies<A: AllocRefV2 + Copy>(
+    a: A,
+    scenario: &Scenario,
+    workload: &Workload,
+    batch_size: usize,
+    histogram: &mut Histogram,
+) {
+    match &scenario.failure {
+        Some(failure) => {
+            let failing = FailingAlloc::new(a, failure.clone(), scenario.seed);
+            record_batches(&failing, scenario, workload, batch_size, histogram)
+        }
+        None => record_batches(a, scenario, workload, batch_size, histogram),
+    }
+}
+
+fn record_batches<A: AllocRefV2 + Copy>(
+    a: A,
+    scenario: &Scenario,
+    workload: &Workload,
+    batch_size: usize,
+    histogram: &mut Histogram,
+) {
+    for start in (0..workload.len()).step_by(batch_size) {
+        let end = (start + batch_size).min(workload.len());
+        let run = run_operations(a, scenario, &workload.batch(start..end));
+        histogram.record_batch(run.elapsed, end - start);
+    }
+}
+
+/// The fraction of the workload's footprint that `bump-undersized` is pre-sized to.
+const UNDERSIZED_FRACTION: usize = 8;
+/// The number of rounds that `measure_chunks` runs the reused arena of `bump-reset` for.
+const RESET_ROUNDS: usize = 4;
+
+/// Creates the arena of a bump allocator for `scenario`, backed by `parent`.
+fn bump_in<A: AllocRef>(scenario: &Scenario, workload: &Workload, parent: A) -> Bump<A> {
+    match scenario.allocator {
+        Allocator::Bump => Bump::with_capacity_in(1024 * scenario.iters, parent),
+        Allocator::BumpUndersized => {
+            Bump::with_capacity_in(workload.footprint() / UNDERSIZED_FRACTION, parent)
+        }
+        _ => Bump::new_in(parent),
+    }
+}
+
+/// Creates a free list that can hold every layout of `workload`.
+fn free_list_for(workload: &Workload) -> FreeList {
+    FreeList::with_capacity(FreeList::capacity_for(workload.all_layouts()))
+}
+
+/// Creates a pool with an object for every layout of `workload`, which `check_arena` has
+/// checked to fit in memory.
+fn pool_for(workload: &Workload) -> Pool {
+    Pool::new(workload.max_layout(), workload.len()).expect("The pool does not fit in memory")
+}
+
+/// Runs `scenario` through its bump arena, backed by a tracking allocator, and collects the
+/// chunks of the arena afterwards. The arena of `bump-reset` is reset and reused for several
+/// rounds. Returns `None` for other allocators.
+fn measure_chunks(scenario: &Scenario, workload: &Workload) -> Option<ChunkStats> {
+    let rounds = match scenario.allocator {
+        Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized => 1,
+        Allocator::BumpReset => RESET_ROUNDS,
+        _ => return None,
+    };
+    let parent = Tracking::new(Global);
+    let mut bump = bump_in(scenario, workload, &parent);
+    let batch = workload.batch(0..workload.len());
+    let mut first_round_requests = 0;
+    for round in 0..rounds {
+        if round > 0 {
+            bump.reset();
+        }
+        run_test(&bump, scenario, &batch);
+        if round == 0 {
+            first_round_requests = parent.stats().allocs;
+        }
+    }
+    Some(ChunkStats::new(
+        &mut bump,
+        &parent.stats(),
+        first_round_requests,
+        rounds,
+    ))
+}
+
+/// Allocates the buffer of the linear allocator up front, outside of the measurements, so that
+/// they never call the system allocator. Other allocators get an empty buffer.
+fn linear_buffer(allocator: Allocator, capacity: usize) -> Vec<MaybeUninit<u8>> {
+    if allocator == Allocator::Linear {
+        vec![MaybeUninit::uninit(); capacity]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Measures `scenario`, using a fresh instance of its allocator for every sample, so earlier
+/// samples cannot exhaust or fragment it. The linear allocator and `bump-reset` are reset
+/// instead. The loop overhead of the baseline is subtracted from every sample, and the event
+/// counts of every run are added to `tally`.
+fn measure_scenario(
+    scenario: &Scenario,
+    workload: &Workload,
+    config: &MeasureConfig,
+    tally: &Tally,
+) -> Summary {
+    let mut buffer = linear_buffer(scenario.allocator, workload.footprint());
+    let mut linear = Linear::new(&mut buffer);
+    let mut arena = Bump::new();
+    let baseline = || tally.baseline(|| run_baseline(scenario, &workload.batch(0..workload.len())));
+    let mut failures = 0;
+    let summary = stats::measure_against(config, scenario.iters, baseline, || {
+        let batch = workload.batch(0..workload.len());
+        let run = tally.sample(|| match scenario.allocator {
+            Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized => {
+                let bump = bump_in(scenario, workload, Global);
+                run_test(&bump, scenario, &batch)
+            }
+            Allocator::BumpReset => {
+                arena.reset();
+                run_test(&arena, scenario, &batch)
+            }
+            Allocator::Global => run_test(Global, scenario, &batch),
+            Allocator::System => run_test(System, scenario, &batch),
+            Allocator::Malloc => run_test(Malloc, scenario, &batch),
+            Allocator::PosixMemalign => run_test(PosixMemalign, scenario, &batch),
+            Allocator::AlignedAlloc => run_test(AlignedAlloc, scenario, &batch),
+            Allocator::FreeList => {
+                let free_list = free_list_for(workload);
+                run_test(&free_list, scenario, &batch)
+            }
+            Allocator::Slab => run_test(&Slab::new(), scenario, &batch),
+            Allocator::Pool => run_test(&pool_for(workload), scenario, &batch),
+            Allocator::Linear => {
+                linear.reset();
+                run_test(&linear, scenario, &batch)
+            }
+        });
+        failures = failures.max(run.failures);
+        run.elapsed
+    });
+    Summary {
+        failures,
+        ..summary
+    }
+}
+
+fn measure_latencies(
+    scenario: &Scenario,
+    workload: &Workload,
+    config: &MeasureConfig,
+    batch_size: usize,
+) -> Histogram {
+    let mut buffer = linear_buffer(scenario.allocator, workload.footprint());
+    let mut linear = Linear::new(&mut buffer);
+    let mut arena = Bump::new();
+    let mut histogram = Histogram::new(batch_size);
+    for _ in 0..config.samples {
+        match scenario.allocator {
+            Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized => {
+                let bump = bump_in(scenario, workload, Global);
+                record_latencies(&bump, scenario, workload, batch_size, &mut histogram);
+            }
+            Allocator::BumpReset => {
+                arena.reset();
+                record_latencies(&arena, scenario, workload, batch_size, &mut histogram);
+            }
+            Allocator::Global => {
+                record_latencies(Global, scenario, workload, batch_size, &mut histogram)
+            }
+            Allocator::System => {
+                record_latencies(System, scenario, workload, batch_size, &mut histogram)
+            }
+            Allocator::Malloc => {
+                record_latencies(Malloc, scenario, workload, batch_size, &mut histogram)
+            }
+            Allocator::PosixMemalign => record_latencies(
+                PosixMemalign,
+                scenario,
+                workload,
+                batch_size,
+                &mut histogram,
+            ),
+            Allocator::AlignedAlloc => {
+                record_latencies(AlignedAlloc, scenario, workload, batch_size, &mut histogram)
+            }
+            Allocator::FreeList => {
+                let free_list = free_list_for(workload);
+                record_latencies(&free_list, scenario, workload, batch_size, &mut histogram);
+            }
+            Allocator::Slab => {
+                let slab = Slab::new();
+                record_latencies(&slab, scenario, workload, batch_size, &mut histogram);
+            }
+            Allocator::Pool => record_latencies(
+                &pool_for(workload),
+                scenario,
+                workload,
+                batch_size,
+                &mut histogram,
+            ),
+            Allocator::Linear => {
+                linear.reset();
+                record_latencies(&linear, scenario, workload, batch_size, &mut histogram);
+            }
+        }
+    }
+    histogram
+}
+
+/// Allocates all layouts of `workload` at once through `a`, and analyzes the addresses that it
+/// returns. Layouts that fail to allocate are left out.
+fn padding_with<A: AllocRefV2>(a: A, workload: &Workload) -> PaddingAnalysis {
+    let allocations: Vec<_> = workload
+        .layouts
+        .iter()
+        .filter_map(|layout| Some((a.alloc(*layout).ok()?, *layout)))
+        .collect();
+    let analysis = PaddingAnalysis::new(&allocations);
+    for (ptr, layout) in allocations {
+        unsafe { a.dealloc(ptr, layout) };
+    }
+    analysis
+}
+
+fn analyze_padding(scenario: &Scenario, workload: &Workload) -> PaddingAnalysis {
+    match scenario.allocator {
+        Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized | Allocator::BumpReset => {
+            padding_with(&bump_in(scenario, workload, Global), workload)
+        }
+        Allocator::Global => padding_with(Global, workload),
+        Allocator::System => padding_with(System, workload),
+        Allocator::Malloc => padding_with(Malloc, workload),
+        Allocator::PosixMemalign => padding_with(PosixMemalign, workload),
+        Allocator::AlignedAlloc => padding_with(AlignedAlloc, workload),
+        Allocator::FreeList => padding_with(&free_list_for(workload), workload),
+        Allocator::Slab => padding_with(&Slab::new(), workload),
+        Allocator::Pool => padding_with(&pool_for(workload), workload),
+        Allocator::Linear => {
+            let mut buffer = linear_buffer(scenario.allocator, workload.footprint());
+            padding_with(&Linear::new(&mut buffer), workload)
+        }
+    }
+}
+
+/// Runs `scenario` once through `a`, wrapped in `Verifying`.
+fn verify<A: AllocRefV2>(a: A, scenario: &Scenario, workload: &Workload) -> Verification {
+    let verifying = Verifying::new(a);
+    run_test(&verifying, scenario, &workload.batch(0..workload.len()));
+    verifying.verification()
+}
+
+/// Runs `scenario` once and checks every pointer that its allocator returns.
+fn verify_scenario(scenario: &Scenario, workload: &Workload) -> Verification {
+    match scenario.allocator {
+        Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized | Allocator::BumpReset => {
+            verify(&bump_in(scenario, workload, Global), scenario, workload)
+        }
+        Allocator::Global => verify(Global, scenario, workload),
+        Allocator::System => verify(System, scenario, workload),
+        Allocator::Malloc => verify(Malloc, scenario, workload),
+        Allocator::PosixMemalign => verify(PosixMemalign, scenario, workload),
+        Allocator::AlignedAlloc => verify(AlignedAlloc, scenario, workload),
+        Allocator::FreeList => {
+            let free_list = free_list_for(workload);
+            verify(&free_list, scenario, workload)
+        }
+        Allocator::Slab => verify(&Slab::new(), scenario, workload),
+        Allocator::Pool => verify(&pool_for(workload), scenario, workload),
+        Allocator::Linear => {
+            let mut buffer = linear_buffer(scenario.allocator, workload.footprint());
+            verify(&Linear::new(&mut buffer), scenario, workload)
+        }
+    }
+}
+
+/// Measures `scenario` with direct calls, as if an oracle dispatched every layout, and prints
+/// what branching on the size costs in comparison.
+fn print_oracle_comparison(scenario: &Scenario, summary: &Summary, config: &MeasureConfig) {
+    let oracle = Scenario {
+        call: Call::Direct,
+        ..scenario.clone()
+    };
+    let baseline = measure_scenario(&oracle, &Workload::new(&oracle), config, &Tally::default());
+    let overhead = summary.mean - baseline.mean;
+    println!(
+        "oracle:   {:.3} us with direct calls; branching costs {:.3} ns per operation ({:+.1}%)",
+        baseline.mean / 1e3,
+        overhead / scenario.iters as f64,
+        overhead / baseline.mean * 100.0
+    );
+}
+
+/// Generates the workload of `scenario`, or exits if its allocator cannot hold it.
+fn workload_for(scenario: &Scenario) -> Workload {
+    let workload = Workload::new(scenario);
+    if let Err(error) = workload.check_arena(scenario.allocator) {
+        eprintln!("error: {}", error);
+        process::exit(2);
+    }
+    workload
+}
+
+/// Measures `scenario` and prints its results. Fails after printing them if calls failed that
+/// were not meant to.
+fn run_scenario(scenario: &Scenario, options: &Options) -> Result<(), String> {
+    let workload = workload_for(scenario);
+    let tally = Tally::default();
+    let summary = measure_scenario(scenario, &workload, &options.config, &tally);
+    let counters = tally.report(scenario.iters);
+
+    if options.format == Format::Record {
+        println!("{}", summary.to_record());
+        return scenario.check_failures(summary.failures);
+    }
+
+    let chunks = measure_chunks(scenario, &workload);
+    let latency = if options.latency_batch > 0 {
+        Some(measure_latencies(
+            scenario,
+            &workload,
+            &options.config,
+            options.latency_batch,
+        ))
+    } else {
+        None
+    };
+
+    match options.format {
+        Format::Text => {
+            println!("== {} ==", scenario);
+            println!("seed:     {}", scenario.seed);
+            println!("clock:    {}", clock::describe());
+            println!("sizes:    {}", scenario.size_dist);
+            println!("aligns:   {}", scenario.align_dist);
+            if let Some(failure) = &scenario.failure {
+                println!("failing:  {}", failure);
+            }
+            if scenario.sizes == Sizes::Mixed {
+                print!(
+                    "zsts:     {}% {}",
+                    scenario.zst_ratio * 100.0,
+                    scenario.pattern.name()
+                );
+                if scenario.pattern == Pattern::Bursty {
+                    print!(", bursts of {}", scenario.burst);
+                }
+                println!();
+            }
+            println!("{}", summary);
+            if let Some(chunks) = &chunks {
+                println!("{}", chunks);
+            }
+            if let Some(counters) = &counters {
+                println!("{}", counters);
+            }
+            match options.analysis {
+                Analysis::None => {}
+                Analysis::Padding => println!("{}", analyze_padding(scenario, &workload)),
+                Analysis::Verify => {
+                    let verification = verify_scenario(scenario, &workload);
+                    println!("{}", verification);
+                    if !verification.passed() {
+                        process::exit(1);
+                    }
+                }
+            }
+            if scenario.sizes == Sizes::Mixed && !scenario.is_direct() {
+                print_oracle_comparison(scenario, &summary, &options.config);
+            }
+            if let Some(histogram) = &latency {
+                println!("{}", histogram);
+            }
+        }
+        Format::Json | Format::Csv => {
+            let record = Record::new(
+                scenario,
+                &options.config,
+                &summary,
+                latency.as_ref(),
+                chunks.as_ref(),
+                counters.as_ref(),
+                &Environment::detect(),
+            );
+            if options.format == Format::Json {
+                println!("{}", record.to_json());
+            } else {
+                println!("{}", record.csv_header());
+                println!("{}", record.to_csv());
+            }
+        }
+        Format::Record => unreachable!(),
+    }
+    scenario.check_failures(summary.failures)
+}
+
+/// Replays the trace at `path` through the selected allocator, using a fresh instance of it for
+/// every sample.
+fn replay_trace(path: &str, options: &Options) -> Result<(), String> {
+    let events = TraceReader::open(path)
+        .and_then(|reader| reader.collect::<io::Result<Vec<_>>>())
+        .map_err(|error| format!("Cannot read trace '{}': {}", path, error))?;
+    let replay = Replay::new(events)?;
+    if replay.stats.allocs == 0 {
+        return Err(format!("Trace '{}' has no allocations.", path));
+    }
+
+    let allocator = options.allocator.unwrap_or(Allocator::Global);
+    let stats = &replay.stats;
+    let too_large = || {
+        format!(
+            "The arena of '{}' cannot hold every layout of trace '{}'.",
+            allocator.name(),
+            path
+        )
+    };
+    // Every allocation may need padding for its alignment.
+    let linear_capacity = match allocator {
+        Allocator::Linear => (stats.allocs + stats.reallocs)
+            .checked_mul(stats.max_align)
+            .and_then(|padding| padding.checked_add(stats.total_bytes))
+            .ok_or_else(too_large)?,
+        _ => 0,
+    };
+    // The objects of a pool fit the largest size and the largest alignment of the trace, which
+    // may belong to different layouts. Other allocators do not use the object layout.
+    let pool_object = match allocator {
+        Allocator::Pool => {
+            let object = Layout::from_size_align(stats.max_size, stats.max_align)
+                .map_err(|_| too_large())?;
+            // A pool is created for every sample, so check up front that it fits in memory.
+            Pool::new(object, stats.peak_objects)?;
+            object
+        }
+        _ => Layout::new::<()>(),
+    };
+    let mut buffer = linear_buffer(allocator, linear_capacity);
+    let free_list_capacity = FreeList::capacity_for(replay.layouts());
+    let mut linear = Linear::new(&mut buffer);
+    let mut arena = Bump::new();
+    let mut failures = 0;
+    let operations = stats.allocs + stats.deallocs + stats.reallocs;
+    let summary = stats::measure(&options.config, operations, || {
+        let run = match allocator {
+            Allocator::Bump => {
+                let bump = Bump::with_capacity(stats.peak_bytes);
+                replay::run(&bump, &replay)
+            }
+            Allocator::BumpNew => replay::run(&Bump::new(), &replay),
+            Allocator::BumpUndersized => {
+                let bump = Bump::with_capacity(stats.peak_bytes / UNDERSIZED_FRACTION);
+                replay::run(&bump, &replay)
+            }
+            Allocator::BumpReset => {
+                arena.reset();
+                replay::run(&arena, &replay)
+            }
+            Allocator::Global => replay::run(Global, &replay),
+            Allocator::System => replay::run(System, &replay),
+            Allocator::Malloc => replay::run(Malloc, &replay),
+            Allocator::PosixMemalign => replay::run(PosixMemalign, &replay),
+            Allocator::AlignedAlloc => replay::run(AlignedAlloc, &replay),
+            Allocator::FreeList => {
+                let free_list = FreeList::with_capacity(free_list_capacity);
+                replay::run(&free_list, &replay)
+            }
+            Allocator::Slab => replay::run(&Slab::new(), &replay),
+            Allocator::Pool => {
+                let pool = Pool::new(pool_object, stats.peak_objects)
+                    .expect("The pool does not fit in memory");
+                replay::run(&pool, &replay)
+            }
+            Allocator::Linear => {
+                linear.reset();
+                replay::run(&linear, &replay)
+            }
+        };
+        failures = failures.max(run.failures);
+        run.elapsed
+    });
+    let summary = Summary {
+        failures,
+        ..summary
+    };
+
+    println!("== replay {} with {} ==", path, allocator.name());
+    println!("{}", replay.stats);
+    println!("{}", summary);
+    // Replays have no failure policy, so like scenarios without one, every call must succeed.
+    if summary.failures > 0 {
+        return Err(format!(
+            "{} calls failed in a sample of trace '{}'; the allocator ran out of memory or \
+             rejected a layout.",
+            summary.failures, path
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `scenario` once through `a`, wrapped in `Tracking`. Injected failures are tracked as
+/// well, so they count as failed calls.
+fn track<A: AllocRefV2>(a: A, scenario: &Scenario, workload: &Workload) -> TrackingStats {
+    let batch = workload.batch(0..workload.len());
+    match &scenario.failure {
+        Some(failure) => {
+            let failing = FailingAlloc::new(a, failure.clone(), scenario.seed);
+            let tracking = Tracking::new(&failing);
+            run_operations(&tracking, scenario, &batch);
+            tracking.stats()
+        }
+        None => {
+            let tracking = Tracking::new(a);
+            run_operations(&tracking, scenario, &batch);
+            tracking.stats()
+        }
+    }
+}
+
+/// Runs `scenario` once and prints the calls, bytes and layouts that reached its allocator. For
+/// bump, the chunks that it requests from its parent allocator are tracked as well.
+fn track_scenario(scenario: &Scenario) {
+    let workload = workload_for(scenario);
+    println!("== track {} ==", scenario);
+    let stats = match scenario.allocator {
+        Allocator::Bump | Allocator::BumpNew | Allocator::BumpUndersized | Allocator::BumpReset => {
+            let parent = Tracking::new(Global);
+            let bump = bump_in(scenario, &workload, &parent);
+            let stats = track(&bump, scenario, &workload);
+            println!("{}", stats);
+            println!("-- chunks requested from the parent allocator --");
+            parent.stats()
+        }
+        Allocator::Global => track(Global, scenario, &workload),
+        Allocator::System => track(System, scenario, &workload),
+        Allocator::Malloc => track(Malloc, scenario, &workload),
+        Allocator::PosixMemalign => track(PosixMemalign, scenario, &workload),
+        Allocator::AlignedAlloc => track(AlignedAlloc, scenario, &workload),
+        Allocator::FreeList => {
+            let free_list = free_list_for(&workload);
+            track(&free_list, scenario, &workload)
+        }
+        Allocator::Slab => track(&Slab::new(), scenario, &workload),
+        Allocator::Pool => track(&pool_for(&workload), scenario, &workload),
+        Allocator::Linear => {
+            let mut buffer = linear_buffer(scenario.allocator, workload.footprint());
+            track(&Linear::new(&mut buffer), scenario, &workload)
+        }
+    };
+    println!("{}", stats);
+}
+
+/// Writes the layouts of `scenario` as a trace: every layout is allocated, resized in the grow
+/// and shrink modes, and deallocated again.
+fn export_trace(path: &str, scenario: &Scenario) -> Result<(), String> {
+    let workload = Workload::new(scenario);
+    let (layouts, resized) = match scenario.mode {
+        Mode::Grow => (&workload.layouts, Some(&workload.grown_layouts)),
+        Mode::Shrink => (&workload.grown_layouts, Some(&workload.layouts)),
+        _ => (&workload.layouts, None),
+    };
+
+    let mut events = Vec::with_capacity(3 * layouts.len());
+    for (id, layout) in layouts.iter().enumerate() {
+        events.push((
+            id,
+            Operation::Alloc {
+                size: layout.size(),
+                align: layout.align(),
+            },
+        ));
+    }
+    for (id, layout) in resized.into_iter().flatten().enumerate() {
+        events.push((
+            id,
+            Operation::Realloc {
+                size: layout.size(),
+                new_id: None,
+            },
+        ));
+    }
+    events.extend((0..layouts.len()).map(|id| (id, Operation::Dealloc)));
+
+    let write = || -> io::Result<()> {
+        let mut writer = TraceWriter::create(path)?;
+        for (id, operation) in events {
+            writer.write(&Event {
+                id: id as u64,
+                thread: None,
+                operation,
+            })?;
+        }
+        writer.flush()
+    };
+    write().map_err(|error| format!("Cannot write trace '{}': {}", path, error))
+}
+
+fn select_clock(clock: Clock) {
+    if let Err(error) = clock::select(clock) {
+        eprintln!("error: {}", error);
+        process::exit(2);
+    }
+}
+
+fn select_counters(counters: Counters) {
+    if let Err(error) = counters::select(counters) {
+        eprintln!("error: {}", error);
+        process::exit(2);
+    }
+}
+
+/// See `cli::USAGE` for the supported commands and options, or run with `help`.
 ///
-/// E.g. `cargo run --release -- 10000000 false false true`
+/// E.g. `cargo run --release -- run --iters 10000000 --allocator bump --sizes zero --call direct`
 fn main() {
-    let iters: usize = env::args()
-        .nth(1)
-        .expect("Expected number of iterations.")
-        .parse()
-        .unwrap();
-
-    let is_bump: bool = env::args()
-        .nth(2)
-        .expect("Expected '0' (global) or '1' (bump) to indicate allocator type.")
-        .parse()
-        .unwrap();
-
-    let is_zero: bool = env::args()
-        .nth(3)
-        .expect("Expected '0' (randomly distributed, non-zero allocations) or '1' (zero-sized allocations) to indicate allocator type.")
-        .parse()
-        .unwrap();
-
-    let is_direct: bool = env::args()
-        .nth(4)
-        .expect("Expected '0' (branched) or '1' (direct) to function call type.")
-        .parse()
-        .unwrap();
-
-    if is_bump {
-        let bump = Bump::with_capacity(1024 * iters);
-        run_test(&bump, iters, is_direct, is_zero);
-    } else {
-        run_test(Global, iters, is_direct, is_zero);
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("error: {}\n\nRun with `help` for usage information.", error);
+            process::exit(2);
+        }
+    };
+
+    match &command {
+        Command::Run(options) | Command::Matrix(options) | Command::Replay(_, options) => {
+            select_clock(options.clock)
+        }
+        _ => {}
+    }
+
+    match command {
+        Command::Run(options) => {
+            select_counters(options.counters);
+            if let Err(error) = run_scenario(&options.scenario(), &options) {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
+        }
+        Command::Matrix(options) => matrix::run(&options, |scenario| {
+            let workload = workload_for(scenario);
+            measure_scenario(scenario, &workload, &options.config, &Tally::default())
+        }),
+        Command::Replay(path, options) => {
+            if let Err(error) = replay_trace(&path, &options) {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
+        }
+        Command::Export(path, options) => {
+            if let Err(error) = export_trace(&path, &options.scenario()) {
+                eprintln!("error: {}", error);
+                process::exit(1);
+            }
+        }
+        Command::Track(options) => track_scenario(&options.scenario()),
+        Command::List => cli::list(),
+        Command::Help => println!("{}\n\n{}", cli::USAGE, distribution::SYNTAX),
     }
 }
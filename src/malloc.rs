@@ -0,0 +1,243 @@
+//! `AllocRefV2` implementations that call the C allocator directly, through FFI declarations,
+//! to compare the platform allocator without any Rust layer on top.
+
+use crate::AllocRefV2;
+use alloc_wg::alloc::{AllocErr, NonZeroLayout};
+use std::alloc::Layout;
+use std::cmp;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr::{self, NonNull};
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn calloc(count: usize, size: usize) -> *mut c_void;
+    fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+    fn posix_memalign(ptr: *mut *mut c_void, align: usize, size: usize) -> c_int;
+    fn aligned_alloc(align: usize, size: usize) -> *mut c_void;
+}
+
+/// The alignment that `malloc` guarantees, as assumed by `std::alloc::System`.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "mips64",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "sparc64"
+))]
+const MIN_ALIGN: usize = 16;
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "mips64",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "sparc64"
+)))]
+const MIN_ALIGN: usize = 8;
+
+/// Whether `malloc` and `realloc` return memory that is aligned well enough for `layout`.
+#[inline(always)]
+fn fits_malloc(layout: Layout) -> bool {
+    layout.align() <= MIN_ALIGN && layout.align() <= layout.size()
+}
+
+#[inline(always)]
+fn to_result(ptr: *mut c_void) -> Result<NonNull<u8>, AllocErr> {
+    NonNull::new(ptr as *mut u8).ok_or(AllocErr)
+}
+
+#[inline(always)]
+unsafe fn posix_memalign_alloc(layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+    let mut ptr = ptr::null_mut();
+    // `posix_memalign` requires the alignment to be a multiple of the size of a pointer.
+    let align = cmp::max(layout.align(), mem::size_of::<usize>());
+    if posix_memalign(&mut ptr, align, layout.size()) == 0 {
+        to_result(ptr)
+    } else {
+        Err(AllocErr)
+    }
+}
+
+#[inline(always)]
+unsafe fn aligned_alloc_alloc(layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+    // C11 requires the size to be a multiple of the alignment.
+    let align = layout.align();
+    let size = (layout.size() + align - 1) & !(align - 1);
+    to_result(aligned_alloc(align, size))
+}
+
+/// Resizes an allocation of `Malloc` with `realloc` if it keeps the alignment, or by
+/// allocating, copying and freeing otherwise.
+#[inline(always)]
+unsafe fn resize(
+    ptr: NonNull<u8>,
+    layout: NonZeroLayout,
+    new_layout: NonZeroLayout,
+) -> Result<NonNull<u8>, AllocErr> {
+    if fits_malloc(new_layout.into()) {
+        let new_size = Layout::from(new_layout).size();
+        return to_result(realloc(ptr.as_ptr() as *mut c_void, new_size));
+    }
+    reallocate(Malloc, ptr, layout, new_layout)
+}
+
+/// Resizes an allocation by allocating through `a`, copying and freeing, so the new allocation
+/// comes from the same C function as every other allocation of `a`.
+#[inline(always)]
+unsafe fn reallocate<A: AllocRefV2>(
+    a: A,
+    ptr: NonNull<u8>,
+    layout: NonZeroLayout,
+    new_layout: NonZeroLayout,
+) -> Result<NonNull<u8>, AllocErr> {
+    let new_ptr = a.alloc_non_zst(new_layout)?;
+    let size = cmp::min(Layout::from(layout).size(), Layout::from(new_layout).size());
+    ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), size);
+    a.dealloc_non_zst(ptr, layout);
+    Ok(new_ptr)
+}
+
+/// libc `malloc`, falling back to `posix_memalign` for alignments that `malloc` does not
+/// guarantee, like `std::alloc::System` does on Unix.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Malloc;
+
+impl AllocRefV2 for Malloc {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        if fits_malloc(layout) {
+            to_result(unsafe { malloc(layout.size()) })
+        } else {
+            unsafe { posix_memalign_alloc(layout) }
+        }
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        if fits_malloc(layout) {
+            to_result(unsafe { calloc(1, layout.size()) })
+        } else {
+            let ptr = unsafe { posix_memalign_alloc(layout) }?;
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+            Ok(ptr)
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, _layout: NonZeroLayout) {
+        free(ptr.as_ptr() as *mut c_void)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        resize(ptr, layout, new_layout)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        resize(ptr, layout, new_layout)
+    }
+}
+
+/// libc `posix_memalign` for every allocation. Resizing allocates, copies and frees, since
+/// `realloc` would return memory from `malloc`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PosixMemalign;
+
+impl AllocRefV2 for PosixMemalign {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        unsafe { posix_memalign_alloc(layout.into()) }
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let ptr = self.alloc_non_zst(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, Layout::from(layout).size()) };
+        Ok(ptr)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, _layout: NonZeroLayout) {
+        free(ptr.as_ptr() as *mut c_void)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        reallocate(self, ptr, layout, new_layout)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        reallocate(self, ptr, layout, new_layout)
+    }
+}
+
+/// C11 `aligned_alloc` for every allocation. Resizing allocates, copies and frees, since
+/// `realloc` would return memory from `malloc`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlignedAlloc;
+
+impl AllocRefV2 for AlignedAlloc {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        unsafe { aligned_alloc_alloc(layout.into()) }
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let ptr = self.alloc_non_zst(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, Layout::from(layout).size()) };
+        Ok(ptr)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, _layout: NonZeroLayout) {
+        free(ptr.as_ptr() as *mut c_void)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        reallocate(self, ptr, layout, new_layout)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        reallocate(self, ptr, layout, new_layout)
+    }
+}
@@ -0,0 +1,195 @@
+use crate::cli::{Format, Options};
+use crate::output::{Environment, Record};
+use crate::scenario::{Choice, Scenario, Sizes};
+use crate::stats::Summary;
+use std::env;
+use std::process::{Command, Stdio};
+
+/// How scenarios of a matrix are kept from influencing each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    Process,
+    InProcess,
+}
+
+impl Choice for Isolation {
+    const WHAT: &'static str = "isolation";
+    const ALL: &'static [Self] = &[Isolation::Process, Isolation::InProcess];
+
+    fn name(self) -> &'static str {
+        match self {
+            Isolation::Process => "process",
+            Isolation::InProcess => "in-process",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Isolation::Process => "run every scenario in a fresh child process",
+            Isolation::InProcess => "run every scenario in this process with fresh allocators",
+        }
+    }
+}
+
+/// Runs every scenario selected by `options` and prints one consolidated table.
+///
+/// `measure` is used to measure a scenario when `options.isolation` is `InProcess`.
+pub fn run<F: Fn(&Scenario) -> Summary>(options: &Options, measure: F) {
+    let scenarios = options.scenarios();
+    let mut rows = Vec::with_capacity(scenarios.len());
+
+    for (index, scenario) in scenarios.iter().enumerate() {
+        eprintln!("[{}/{}] {}", index + 1, scenarios.len(), scenario);
+        let result = match options.isolation {
+            Isolation::Process => measure_in_child(scenario, options),
+            Isolation::InProcess => Ok(measure(scenario)),
+        }
+        .and_then(|summary| {
+            scenario.check_failures(summary.failures)?;
+            Ok(summary)
+        });
+        match result {
+            Ok(summary) => {
+                if summary.near_baseline() {
+                    eprintln!(
+                        "warning: {} is close to its baseline; it may have been optimized away",
+                        scenario
+                    );
+                }
+                rows.push((scenario.clone(), summary))
+            }
+            Err(error) => eprintln!("error: {}", error),
+        }
+    }
+
+    match options.format {
+        Format::Text | Format::Record => print_table(&rows),
+        Format::Json | Format::Csv => print_records(&rows, options),
+    }
+}
+
+fn print_records(rows: &[(Scenario, Summary)], options: &Options) {
+    let environment = Environment::detect();
+    for (index, (scenario, summary)) in rows.iter().enumerate() {
+        let record = Record::new(
+            scenario,
+            &options.config,
+            summary,
+            None,
+            None,
+            None,
+            &environment,
+        );
+        if options.format == Format::Json {
+            println!("{}", record.to_json());
+        } else {
+            if index == 0 {
+                println!("{}", record.csv_header());
+            }
+            println!("{}", record.to_csv());
+        }
+    }
+}
+
+/// Runs `scenario` in a child process of the current executable, which reports back its
+/// summary as a single record.
+fn measure_in_child(scenario: &Scenario, options: &Options) -> Result<Summary, String> {
+    let exe = env::current_exe()
+        .map_err(|error| format!("Cannot locate the current executable: {}", error))?;
+
+    let mut args = vec![
+        "run".to_owned(),
+        format!("--iters={}", scenario.iters),
+        format!("--seed={}", scenario.seed),
+        format!("--size-dist={}", scenario.size_dist),
+        format!("--align-dist={}", scenario.align_dist),
+        format!("--zst-ratio={}", scenario.zst_ratio),
+        format!("--pattern={}", scenario.pattern.name()),
+        format!("--burst={}", scenario.burst),
+        format!("--allocator={}", scenario.allocator.name()),
+        format!("--sizes={}", scenario.sizes.name()),
+        format!("--call={}", scenario.call.name()),
+        format!("--mode={}", scenario.mode.name()),
+        format!("--samples={}", options.config.samples),
+        format!("--warmup={}", options.config.warmup),
+        format!("--resamples={}", options.config.resamples),
+        format!("--confidence={}", options.config.confidence),
+        format!("--clock={}", options.clock.name()),
+        "--format=record".to_owned(),
+    ];
+    if let Some(failure) = &scenario.failure {
+        args.push(format!("--fail={}", failure));
+    }
+    let output = Command::new(exe)
+        .args(args)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| format!("Cannot spawn a child process: {}", error))?;
+
+    if !output.status.success() {
+        return Err(format!("'{}' failed with {}.", scenario, output.status));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let record = stdout
+        .lines()
+        .last()
+        .ok_or_else(|| format!("'{}' did not report a summary.", scenario))?;
+    Summary::from_record(record)
+}
+
+fn print_table(rows: &[(Scenario, Summary)]) {
+    println!(
+        "{:<16} {:<8} {:<9} {:<9} {:<14} {:>14} {:>10} {:>27} {:>14} {:>14} {:>8}",
+        "allocator",
+        "sizes",
+        "pattern",
+        "call",
+        "mode",
+        "mean (us)",
+        "ns/op",
+        "CI (us)",
+        "median (us)",
+        "std dev (us)",
+        "vs best"
+    );
+
+    for (scenario, summary) in rows {
+        // Compare against the fastest scenario that performed the same work. The means before
+        // subtracting the baseline are compared, since a mean close to its baseline can be zero
+        // or negative after subtracting it.
+        let best = rows
+            .iter()
+            .filter(|(other, _)| {
+                other.sizes == scenario.sizes
+                    && other.pattern == scenario.pattern
+                    && other.mode == scenario.mode
+            })
+            .map(|(_, other)| other.mean + other.baseline)
+            .fold(f64::INFINITY, f64::min);
+        let ratio = if best > 0.0 {
+            format!("{:.2}x", (summary.mean + summary.baseline) / best)
+        } else {
+            "-".to_owned()
+        };
+
+        println!(
+            "{:<16} {:<8} {:<9} {:<9} {:<14} {:>14.3} {:>10.3} {:>27} {:>14.3} {:>14.3} {:>8}",
+            scenario.allocator.name(),
+            scenario.sizes.name(),
+            if scenario.sizes == Sizes::Mixed {
+                scenario.pattern.name()
+            } else {
+                "-"
+            },
+            scenario.call.name(),
+            scenario.mode.name(),
+            summary.mean / 1e3,
+            summary.per_operation(summary.mean),
+            format!("[{:.3}, {:.3}]", summary.ci.0 / 1e3, summary.ci.1 / 1e3),
+            summary.median / 1e3,
+            summary.std_dev / 1e3,
+            ratio
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use crate::arena::ChunkStats;
+use crate::clock;
+use crate::counters::{Event, Report};
+use crate::histogram::Histogram;
+use crate::scenario::{Choice, Scenario};
+use crate::stats::{MeasureConfig, Summary};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The percentiles of a latency histogram that are included in a record.
+const LATENCY_PERCENTILES: [(&str, f64); 4] = [
+    ("latency_p50_ns", 50.0),
+    ("latency_p90_ns", 90.0),
+    ("latency_p99_ns", 99.0),
+    ("latency_p999_ns", 99.9),
+];
+
+/// A value of a record field.
+pub enum Value {
+    Str(String),
+    Int(u64),
+    Float(f64),
+}
+
+/// Information about the machine and build that produced a result.
+pub struct Environment {
+    pub timestamp: u64,
+    pub host: String,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub cpu: String,
+    pub cpus: u64,
+    pub version: &'static str,
+    pub profile: &'static str,
+}
+
+impl Environment {
+    pub fn detect() -> Self {
+        let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+        let cpu = cpuinfo
+            .lines()
+            .find(|line| line.starts_with("model name"))
+            .and_then(|line| line.find(':').map(|colon| &line[colon + 1..]))
+            .map_or_else(|| "unknown".to_owned(), |model| model.trim().to_owned());
+        let cpus = cpuinfo
+            .lines()
+            .filter(|line| line.starts_with("processor"))
+            .count() as u64;
+
+        let host = fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .or_else(|| env::var("HOSTNAME").ok())
+            .map_or_else(|| "unknown".to_owned(), |host| host.trim().to_owned());
+
+        Environment {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            host,
+            os: env::consts::OS,
+            arch: env::consts::ARCH,
+            cpu,
+            cpus,
+            version: env!("CARGO_PKG_VERSION"),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+        }
+    }
+}
+
+/// The result of a single scenario, as an ordered list of named fields.
+pub struct Record {
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Record {
+    pub fn new(
+        scenario: &Scenario,
+        config: &MeasureConfig,
+        summary: &Summary,
+        latency: Option<&Histogram>,
+        chunks: Option<&ChunkStats>,
+        counters: Option<&Report>,
+        environment: &Environment,
+    ) -> Self {
+        let mut fields = vec![
+            (
+                "allocator",
+                Value::Str(scenario.allocator.name().to_owned()),
+            ),
+            ("sizes", Value::Str(scenario.sizes.name().to_owned())),
+            ("call", Value::Str(scenario.call.name().to_owned())),
+            ("mode", Value::Str(scenario.mode.name().to_owned())),
+            ("iters", Value::Int(scenario.iters as u64)),
+            ("seed", Value::Int(scenario.seed)),
+            ("size_dist", Value::Str(scenario.size_dist.to_string())),
+            ("align_dist", Value::Str(scenario.align_dist.to_string())),
+            ("zst_ratio", Value::Float(scenario.zst_ratio)),
+            ("pattern", Value::Str(scenario.pattern.name().to_owned())),
+            ("burst", Value::Int(scenario.burst as u64)),
+            (
+                "failure",
+                Value::Str(
+                    scenario
+                        .failure
+                        .as_ref()
+                        .map_or("none".to_owned(), |f| f.to_string()),
+                ),
+            ),
+            ("warmup", Value::Int(config.warmup as u64)),
+            ("clock", Value::Str(clock::describe())),
+            ("samples", Value::Int(summary.samples as u64)),
+            ("operations", Value::Int(summary.operations as u64)),
+            ("failures", Value::Int(summary.failures as u64)),
+            ("mean_ns", Value::Float(summary.mean)),
+            (
+                "mean_ns_per_op",
+                Value::Float(summary.per_operation(summary.mean)),
+            ),
+            ("median_ns", Value::Float(summary.median)),
+            (
+                "median_ns_per_op",
+                Value::Float(summary.per_operation(summary.median)),
+            ),
+            ("std_dev_ns", Value::Float(summary.std_dev)),
+            ("min_ns", Value::Float(summary.min)),
+            ("max_ns", Value::Float(summary.max)),
+            ("ci_low_ns", Value::Float(summary.ci.0)),
+            ("ci_high_ns", Value::Float(summary.ci.1)),
+            ("confidence", Value::Float(summary.confidence)),
+            ("outliers", Value::Int(summary.outliers.total() as u64)),
+            ("baseline_ns", Value::Float(summary.baseline)),
+        ];
+
+        // Latency fields are always present, so every CSV row has the same columns.
+        let batch = latency.map_or(0, |histogram| histogram.batch() as u64);
+        fields.push(("latency_batch", Value::Int(batch)));
+        for (name, p) in LATENCY_PERCENTILES.iter() {
+            let value = latency.map_or(0, |histogram| histogram.percentile(*p));
+            fields.push((name, Value::Int(value)));
+        }
+        let max = latency.map_or(0, |histogram| histogram.max());
+        fields.push(("latency_max_ns", Value::Int(max)));
+
+        // Likewise, the chunk fields are zero for allocators other than bump arenas.
+        let chunk_field =
+            |field: fn(&ChunkStats) -> usize| Value::Int(chunks.map_or(0, field) as u64);
+        fields.extend(vec![
+            ("bump_chunks", chunk_field(|chunks| chunks.chunks)),
+            ("bump_chunk_bytes", chunk_field(|chunks| chunks.chunk_bytes)),
+            ("bump_unused_bytes", chunk_field(ChunkStats::unused_bytes)),
+        ]);
+
+        // The counter fields are NaN if they were not counted, since zero is a valid count.
+        for &event in Event::ALL.iter() {
+            let count = counters.map_or(f64::NAN, |report| report.per_allocation(event));
+            fields.push((event.field(), Value::Float(count)));
+        }
+
+        fields.extend(vec![
+            ("timestamp", Value::Int(environment.timestamp)),
+            ("host", Value::Str(environment.host.clone())),
+            ("os", Value::Str(environment.os.to_owned())),
+            ("arch", Value::Str(environment.arch.to_owned())),
+            ("cpu", Value::Str(environment.cpu.clone())),
+            ("cpus", Value::Int(environment.cpus)),
+            ("version", Value::Str(environment.version.to_owned())),
+            ("profile", Value::Str(environment.profile.to_owned())),
+        ]);
+
+        Record { fields }
+    }
+
+    /// Formats the record as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        for (index, (name, value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            write!(json, "\"{}\":", name).unwrap();
+            match value {
+                Value::Str(s) => write_json_string(&mut json, s),
+                Value::Int(i) => write!(json, "{}", i).unwrap(),
+                // JSON has no representation for NaN or infinity.
+                Value::Float(f) if !f.is_finite() => json.push_str("null"),
+                Value::Float(f) => write!(json, "{}", f).unwrap(),
+            }
+        }
+        json.push('}');
+        json
+    }
+
+    /// The CSV header that matches `to_csv`.
+    pub fn csv_header(&self) -> String {
+        let names: Vec<_> = self.fields.iter().map(|(name, _)| *name).collect();
+        names.join(",")
+    }
+
+    pub fn to_csv(&self) -> String {
+        let values: Vec<_> = self
+            .fields
+            .iter()
+            .map(|(_, value)| match value {
+                Value::Str(s) => csv_escape(s),
+                Value::Int(i) => i.to_string(),
+                Value::Float(f) => f.to_string(),
+            })
+            .collect();
+        values.join(",")
+    }
+}
+
+fn write_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_escape, write_json_string, Environment, Record, Value};
+    use crate::cli::Options;
+    use crate::counters::Event;
+    use crate::stats::{MeasureConfig, Summary};
+
+    fn json_string(s: &str) -> String {
+        let mut json = String::new();
+        write_json_string(&mut json, s);
+        json
+    }
+
+    /// Splits a CSV row into its fields, keeping quoted fields intact.
+    fn csv_fields(row: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = row.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        assert!(!quoted, "unterminated quote in '{}'", row);
+        fields
+    }
+
+    /// A record of the default scenario with awkward strings and no event counters.
+    fn record() -> Record {
+        let options = Options {
+            size_dist: "table:8=1,16=3".parse().unwrap(),
+            ..Options::default()
+        };
+        let config = MeasureConfig::default();
+        let summary = Summary::new(&[1.0, 2.0, 4.0], 10, &config);
+        let environment = Environment {
+            timestamp: 1,
+            host: "tab\there\u{1}".to_owned(),
+            os: "linux",
+            arch: "x86_64",
+            cpu: "The \"Fast\" CPU, C:\\ edition\n".to_owned(),
+            cpus: 4,
+            version: "0.1.0",
+            profile: "debug",
+        };
+        Record::new(
+            &options.scenario(),
+            &config,
+            &summary,
+            None,
+            None,
+            None,
+            &environment,
+        )
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+        assert_eq!(json_string("\n\r\t"), "\"\\n\\r\\t\"");
+        assert_eq!(json_string("\u{0}\u{1f}\u{7f}"), "\"\\u0000\\u001f\u{7f}\"");
+        assert_eq!(json_string("é"), "\"é\"");
+    }
+
+    #[test]
+    fn json_records_escape_strings_and_map_non_finite_numbers_to_null() {
+        let json = record().to_json();
+        assert!(json.starts_with("{\"allocator\":\"global\","), "{}", json);
+        assert!(json.ends_with('}'), "{}", json);
+        assert!(json.contains("\"host\":\"tab\\there\\u0001\""), "{}", json);
+        assert!(
+            json.contains("\"cpu\":\"The \\\"Fast\\\" CPU, C:\\\\ edition\\n\""),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains("\"size_dist\":\"table:8=1,16=3\""),
+            "{}",
+            json
+        );
+        assert!(json.contains("\"median_ns\":2,"), "{}", json);
+        for event in Event::ALL.iter() {
+            assert!(
+                json.contains(&format!("\"{}\":null", event.field())),
+                "{}",
+                json
+            );
+        }
+
+        let record = Record {
+            fields: vec![
+                ("nan", Value::Float(f64::NAN)),
+                ("infinity", Value::Float(f64::INFINITY)),
+                ("half", Value::Float(0.5)),
+                ("count", Value::Int(3)),
+            ],
+        };
+        assert_eq!(
+            record.to_json(),
+            "{\"nan\":null,\"infinity\":null,\"half\":0.5,\"count\":3}"
+        );
+    }
+
+    #[test]
+    fn csv_values_with_commas_and_quotes_are_quoted() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn csv_header_matches_the_values() {
+        let record = record();
+        let header = record.csv_header();
+        let values = csv_fields(&record.to_csv());
+        assert_eq!(header.split(',').count(), values.len());
+
+        let names: Vec<_> = header.split(',').collect();
+        let value = |name| &values[names.iter().position(|n| *n == name).unwrap()];
+        assert_eq!(value("size_dist"), "table:8=1,16=3");
+        assert_eq!(value("cpu"), "The \"Fast\" CPU, C:\\ edition\n");
+        assert_eq!(value("samples"), "3");
+    }
+}
@@ -0,0 +1,198 @@
+//! Analysis of the memory that an allocator spends on alignment padding and other overhead,
+//! derived from the addresses that it returns.
+
+use std::alloc::Layout;
+use std::fmt;
+use std::mem;
+use std::ptr::NonNull;
+
+/// Gaps between allocations of at least this many bytes are taken to separate regions of memory,
+/// like the chunks of an arena, rather than to be overhead of the allocations around them.
+const REGION_GAP: usize = 4096;
+
+/// The number of alignment classes, one per power of two.
+const ALIGN_CLASSES: usize = mem::size_of::<usize>() * 8;
+
+#[derive(Clone, Copy, Default)]
+struct AlignClass {
+    allocations: usize,
+    padding: usize,
+}
+
+/// Where the memory between allocations goes.
+///
+/// Allocations are sorted by address. The gap between two neighbours is attributed to the one
+/// that was allocated later, because that allocation was placed with the other one already in
+/// place, whichever direction the allocator bumps in. A gap that is smaller than its alignment
+/// is padding; a larger one is other overhead, like headers or rounding to size classes.
+pub struct PaddingAnalysis {
+    allocations: usize,
+    zero_sized: usize,
+    requested: usize,
+    classes: [AlignClass; ALIGN_CLASSES],
+    overhead: usize,
+    regions: usize,
+    footprint: usize,
+}
+
+impl PaddingAnalysis {
+    /// Analyzes live allocations, in the order in which they were allocated. Zero-sized
+    /// allocations own no memory, so they are only counted.
+    pub fn new(allocations: &[(NonNull<u8>, Layout)]) -> Self {
+        let mut analysis = PaddingAnalysis {
+            allocations: allocations.len(),
+            zero_sized: 0,
+            requested: 0,
+            classes: [AlignClass::default(); ALIGN_CLASSES],
+            overhead: 0,
+            regions: 0,
+            footprint: 0,
+        };
+
+        let mut sorted: Vec<_> = allocations
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, layout))| layout.size() > 0)
+            .map(|(order, (ptr, layout))| (ptr.as_ptr() as usize, *layout, order))
+            .collect();
+        analysis.zero_sized = allocations.len() - sorted.len();
+        sorted.sort_unstable_by_key(|(address, _, _)| *address);
+
+        let mut previous: Option<(usize, usize)> = None;
+        for (address, layout, order) in sorted {
+            analysis.requested += layout.size();
+            analysis.classes[layout.align().trailing_zeros() as usize].allocations += 1;
+
+            match previous {
+                Some((end, previous_order)) if address >= end && address - end < REGION_GAP => {
+                    let gap = address - end;
+                    let later = if order > previous_order {
+                        layout
+                    } else {
+                        allocations[previous_order].1
+                    };
+                    if gap < later.align() {
+                        analysis.classes[later.align().trailing_zeros() as usize].padding += gap;
+                    } else {
+                        analysis.overhead += gap;
+                    }
+                    analysis.footprint += gap + layout.size();
+                }
+                _ => {
+                    analysis.regions += 1;
+                    analysis.footprint += layout.size();
+                }
+            }
+            previous = Some((address + layout.size(), order));
+        }
+        analysis
+    }
+
+    fn padding(&self) -> usize {
+        self.classes.iter().map(|class| class.padding).sum()
+    }
+
+    /// The fraction of the footprint that is not requested memory.
+    fn fragmentation(&self) -> f64 {
+        if self.footprint == 0 {
+            0.0
+        } else {
+            1.0 - self.requested as f64 / self.footprint as f64
+        }
+    }
+}
+
+impl fmt::Display for PaddingAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "memory:   {} bytes requested by {} allocations ({} zero-sized)",
+            self.requested, self.allocations, self.zero_sized
+        )?;
+        writeln!(
+            f,
+            "          {} bytes of padding, {} bytes of other overhead",
+            self.padding(),
+            self.overhead
+        )?;
+        writeln!(
+            f,
+            "          {} bytes of footprint in {} region(s); fragmentation {:.1}%",
+            self.footprint,
+            self.regions,
+            self.fragmentation() * 100.0
+        )?;
+        write!(f, "padding:  align   allocations   bytes   per allocation")?;
+        for (exponent, class) in self.classes.iter().enumerate() {
+            if class.allocations > 0 {
+                write!(
+                    f,
+                    "\n          {:>5}   {:>11}   {:>5}   {:>14.2}",
+                    1usize << exponent,
+                    class.allocations,
+                    class.padding,
+                    class.padding as f64 / class.allocations as f64
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PaddingAnalysis, REGION_GAP};
+    use std::alloc::Layout;
+    use std::ptr::NonNull;
+
+    fn allocation(address: usize, size: usize, align: usize) -> (NonNull<u8>, Layout) {
+        (
+            NonNull::new(address as *mut u8).unwrap(),
+            Layout::from_size_align(size, align).unwrap(),
+        )
+    }
+
+    #[test]
+    fn upward_bumping_pads_the_later_allocation() {
+        let analysis = PaddingAnalysis::new(&[
+            allocation(0x1000, 3, 1),
+            allocation(0x1008, 8, 8),
+            allocation(0x1010, 1, 1),
+            allocation(0x1020, 16, 16),
+        ]);
+        assert_eq!(analysis.requested, 28);
+        assert_eq!(analysis.classes[3].padding, 5);
+        assert_eq!(analysis.classes[4].padding, 15);
+        assert_eq!(analysis.overhead, 0);
+        assert_eq!((analysis.regions, analysis.footprint), (1, 0x30));
+    }
+
+    #[test]
+    fn downward_bumping_pads_the_later_allocation() {
+        let analysis = PaddingAnalysis::new(&[
+            allocation(0x2000, 3, 1),
+            allocation(0x1ff0, 4, 16),
+            allocation(0x1fe8, 2, 8),
+        ]);
+        // 0x1ff4..0x2000 lies below the first allocation and is padding of the second one.
+        assert_eq!(analysis.classes[4].padding, 12);
+        assert_eq!(analysis.classes[3].padding, 6);
+        assert_eq!(analysis.footprint, 0x2003 - 0x1fe8);
+    }
+
+    #[test]
+    fn large_gaps_separate_regions_and_zero_sizes_are_counted() {
+        let analysis = PaddingAnalysis::new(&[
+            allocation(0x10000, 32, 8),
+            // A 16-byte header is not explained by the alignment.
+            allocation(0x10030, 32, 8),
+            allocation(0x10050 + REGION_GAP, 32, 8),
+            allocation(8, 0, 8),
+        ]);
+        assert_eq!(analysis.overhead, 16);
+        assert_eq!(analysis.regions, 2);
+        assert_eq!(analysis.footprint, 32 + 16 + 32 + 32);
+        assert_eq!(analysis.zero_sized, 1);
+        assert!((analysis.fragmentation() - 16.0 / 112.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,213 @@
+//! A pool of fixed-size objects.
+
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::cmp;
+use std::mem;
+use std::ptr::{self, NonNull};
+
+/// A free object, linked to the next free object.
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Serves allocations from a fixed number of equally sized objects, allocated up front. Any
+/// layout that fits an object can be allocated; larger layouts and allocations beyond the
+/// capacity fail.
+pub struct Pool {
+    objects: NonNull<u8>,
+    /// The layout of a single object.
+    object: Layout,
+    capacity: usize,
+    free: Cell<*mut FreeObject>,
+}
+
+impl Pool {
+    /// Creates a pool of `capacity` objects that fit `object`, or fails if the objects do not
+    /// fit in memory.
+    pub fn new(object: Layout, capacity: usize) -> Result<Self, String> {
+        let capacity = cmp::max(capacity, 1);
+        let (object, layout) = Pool::layouts(object, capacity).ok_or_else(|| {
+            format!(
+                "A pool of {} objects of {} bytes, aligned to {}, does not fit in memory.",
+                capacity,
+                object.size(),
+                object.align()
+            )
+        })?;
+        let objects = NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+
+        let mut next = ptr::null_mut();
+        for index in (0..capacity).rev() {
+            let free = unsafe { objects.as_ptr().add(index * object.size()) } as *mut FreeObject;
+            unsafe { free.write(FreeObject { next }) };
+            next = free;
+        }
+
+        Ok(Pool {
+            objects,
+            object,
+            capacity,
+            free: Cell::new(next),
+        })
+    }
+
+    /// The number of bytes that a pool of `capacity` objects that fit `object` allocates up
+    /// front, or `None` if they do not fit in memory.
+    pub fn footprint(object: Layout, capacity: usize) -> Option<usize> {
+        Pool::layouts(object, cmp::max(capacity, 1)).map(|(_, layout)| layout.size())
+    }
+
+    /// The layout of a single object that fits `object`, and the layout of `capacity` of them.
+    fn layouts(object: Layout, capacity: usize) -> Option<(Layout, Layout)> {
+        // Every object needs room for the link of a free object.
+        let align = cmp::max(object.align(), mem::align_of::<FreeObject>());
+        let size = cmp::max(object.size(), mem::size_of::<FreeObject>());
+        let object = Layout::from_size_align(size, align).ok()?.pad_to_align();
+        let size = object.size().checked_mul(capacity)?;
+        let layout = Layout::from_size_align(size, align).ok()?;
+        Some((object, layout))
+    }
+
+    fn fits(&self, layout: Layout) -> bool {
+        layout.size() <= self.object.size() && layout.align() <= self.object.align()
+    }
+
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let free = self.free.get();
+        if free.is_null() || !self.fits(layout) {
+            return Err(AllocErr);
+        }
+        self.free.set(unsafe { (*free).next });
+        Ok(unsafe { NonNull::new_unchecked(free as *mut u8) })
+    }
+
+    unsafe fn release(&self, ptr: NonNull<u8>) {
+        let free = ptr.as_ptr() as *mut FreeObject;
+        let start = self.objects.as_ptr() as usize;
+        debug_assert!(
+            free as usize >= start && (free as usize) < start + self.object.size() * self.capacity,
+            "Not an object of this pool"
+        );
+        free.write(FreeObject {
+            next: self.free.get(),
+        });
+        self.free.set(free);
+    }
+
+    /// Objects are fixed in size, so resizing succeeds in place or not at all.
+    fn resize(&self, ptr: NonNull<u8>, new_layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        if self.fits(new_layout) {
+            Ok(ptr)
+        } else {
+            Err(AllocErr)
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        let layout =
+            Layout::from_size_align(self.object.size() * self.capacity, self.object.align())
+                .unwrap();
+        unsafe { alloc::dealloc(self.objects.as_ptr(), layout) };
+    }
+}
+
+impl AllocRef for &Pool {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.allocate(layout.into())
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        let ptr = self.allocate(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        Ok(ptr)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, _layout: NonZeroLayout) {
+        self.release(ptr)
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        _layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.resize(ptr, new_layout.into())
+    }
+}
+
+crate::impl_alloc_ref_v2!(AllocRef for &Pool);
+
+#[cfg(test)]
+mod tests {
+    use super::Pool;
+    use crate::conformance::layout;
+    use crate::AllocRefV2;
+    use std::ptr;
+
+    #[test]
+    fn objects_are_aligned_and_disjoint() {
+        let pool = Pool::new(layout(48, 16), 100).unwrap();
+        let ptrs: Vec<_> = (0..100)
+            .map(|i| pool.alloc(layout(1 + i % 48, 1 << (i % 5))).unwrap())
+            .collect();
+
+        for (i, ptr) in ptrs.iter().enumerate() {
+            assert_eq!(ptr.as_ptr() as usize % 16, 0);
+            unsafe { ptr::write_bytes(ptr.as_ptr(), i as u8, 48) };
+        }
+        for (i, ptr) in ptrs.iter().enumerate() {
+            let bytes = unsafe { std::slice::from_raw_parts(ptr.as_ptr(), 48) };
+            assert!(bytes.iter().all(|byte| *byte == i as u8));
+            unsafe { pool.dealloc(*ptr, layout(48, 16)) };
+        }
+    }
+
+    #[test]
+    fn exhaustion_and_oversized_layouts_return_errors() {
+        let pool = Pool::new(layout(32, 8), 2).unwrap();
+        assert!(pool.alloc(layout(33, 8)).is_err());
+        assert!(pool.alloc(layout(8, 16)).is_err());
+
+        let a = pool.alloc(layout(32, 8)).unwrap();
+        let b = pool.alloc(layout(32, 8)).unwrap();
+        assert!(pool.alloc(layout(32, 8)).is_err());
+
+        unsafe { pool.dealloc(a, layout(32, 8)) };
+        assert_eq!(pool.alloc(layout(16, 8)).unwrap(), a);
+        unsafe {
+            pool.dealloc(a, layout(16, 8));
+            pool.dealloc(b, layout(32, 8));
+        }
+    }
+
+    #[test]
+    fn resizing_stays_within_an_object() {
+        let pool = Pool::new(layout(64, 8), 1).unwrap();
+        let ptr = pool.alloc(layout(16, 8)).unwrap();
+        unsafe {
+            assert_eq!(pool.grow(ptr, layout(16, 8), 64).unwrap(), ptr);
+            assert!(pool.grow(ptr, layout(64, 8), 65).is_err());
+            assert_eq!(pool.shrink(ptr, layout(64, 8), 1).unwrap(), ptr);
+            pool.dealloc(ptr, layout(1, 8));
+        }
+    }
+
+    #[test]
+    fn pools_that_do_not_fit_in_memory_are_rejected() {
+        assert!(Pool::new(layout(1 << 20, 8), usize::MAX / (1 << 19)).is_err());
+        assert!(Pool::new(layout(isize::MAX as usize / 2 + 1, 8), 2).is_err());
+        assert_eq!(
+            Pool::footprint(layout(1 << 20, 8), usize::MAX / (1 << 19)),
+            None
+        );
+        assert_eq!(Pool::footprint(layout(1, 1), 0), Some(8));
+        assert_eq!(Pool::footprint(layout(24, 16), 3), Some(96));
+    }
+}
@@ -0,0 +1,198 @@
+//! A small property-testing engine: it checks a property against random inputs, and shrinks
+//! the first input that fails it to a minimal one.
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::fmt::Debug;
+
+/// Simpler variants of a failing input, which are tried in order while shrinking.
+pub trait Shrink: Sized {
+    /// The candidates, simplest first. An input without simpler variants returns none.
+    fn shrink(&self) -> Vec<Self>;
+}
+
+impl Shrink for usize {
+    fn shrink(&self) -> Vec<Self> {
+        let mut candidates = Vec::new();
+        if *self > 0 {
+            candidates.push(0);
+        }
+        if *self > 2 {
+            candidates.push(*self / 2);
+        }
+        if *self > 1 {
+            candidates.push(*self - 1);
+        }
+        candidates
+    }
+}
+
+impl Shrink for bool {
+    fn shrink(&self) -> Vec<Self> {
+        if *self {
+            vec![false]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Sequences shrink by removing ever smaller runs of elements, then by shrinking single ones.
+impl<T: Shrink + Clone> Shrink for Vec<T> {
+    fn shrink(&self) -> Vec<Self> {
+        let mut candidates = Vec::new();
+        let mut run = self.len();
+        while run > 0 {
+            for start in (0..self.len()).step_by(run) {
+                let end = (start + run).min(self.len());
+                let mut candidate = self[..start].to_vec();
+                candidate.extend_from_slice(&self[end..]);
+                candidates.push(candidate);
+            }
+            run /= 2;
+        }
+        for (index, element) in self.iter().enumerate() {
+            for simpler in element.shrink() {
+                let mut candidate = self.clone();
+                candidate[index] = simpler;
+                candidates.push(candidate);
+            }
+        }
+        candidates
+    }
+}
+
+/// How thoroughly a property is checked.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The number of random inputs to check.
+    pub cases: usize,
+    /// The seed of the first case; case `i` is generated from `seed + i`.
+    pub seed: u64,
+    /// The maximum number of simpler failing inputs to step through while shrinking.
+    pub max_shrinks: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            cases: 100,
+            seed: 0,
+            max_shrinks: 1000,
+        }
+    }
+}
+
+/// Checks `property` against `config.cases` inputs from `generate`. If one fails, it is shrunk
+/// to an input that fails while none of its candidates do, and the test panics with it.
+pub fn check<T, G, P>(config: &Config, mut generate: G, mut property: P)
+where
+    T: Shrink + Clone + Debug,
+    G: FnMut(&mut ChaCha8Rng) -> T,
+    P: FnMut(&T) -> Result<(), String>,
+{
+    for case in 0..config.cases {
+        let seed = config.seed + case as u64;
+        let input = generate(&mut ChaCha8Rng::seed_from_u64(seed));
+        if let Err(error) = property(&input) {
+            let (input, error, shrinks) = shrink(config, input, error, &mut property);
+            panic!(
+                "Property failed for the input of seed {}, shrunk in {} steps to\n{:#?}\n{}",
+                seed, shrinks, input, error
+            );
+        }
+    }
+}
+
+/// Repeatedly replaces the failing input with its first candidate that fails as well.
+fn shrink<T, P>(
+    config: &Config,
+    mut input: T,
+    mut error: String,
+    property: &mut P,
+) -> (T, String, usize)
+where
+    T: Shrink + Clone,
+    P: FnMut(&T) -> Result<(), String>,
+{
+    let mut shrinks = 0;
+    'shrinking: while shrinks < config.max_shrinks {
+        for candidate in input.shrink() {
+            if let Err(candidate_error) = property(&candidate) {
+                input = candidate;
+                error = candidate_error;
+                shrinks += 1;
+                continue 'shrinking;
+            }
+        }
+        break;
+    }
+    (input, error, shrinks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, shrink, Config, Shrink};
+    use rand::Rng;
+
+    #[test]
+    fn sequences_shrink_to_a_minimal_counterexample() {
+        // Fails whenever any element is at least 10.
+        let mut property = |input: &Vec<usize>| match input.iter().find(|n| **n >= 10) {
+            Some(n) => Err(format!("{} is too large", n)),
+            None => Ok(()),
+        };
+        let input = vec![3, 17, 250, 4, 1];
+        let (shrunk, error, _) = shrink(&Config::default(), input, String::new(), &mut property);
+        assert_eq!(shrunk, vec![10]);
+        assert_eq!(error, "10 is too large");
+    }
+
+    #[test]
+    fn candidates_are_simpler() {
+        assert_eq!(5usize.shrink(), vec![0, 2, 4]);
+        assert!(0usize.shrink().is_empty());
+        assert_eq!(true.shrink(), vec![false]);
+        let candidates = vec![1usize, 2].shrink();
+        assert_eq!(candidates[..3], [vec![], vec![2], vec![1]]);
+        assert!(candidates.contains(&vec![0, 2]));
+    }
+
+    #[test]
+    fn passing_properties_do_not_panic() {
+        let mut cases = 0;
+        check(
+            &Config::default(),
+            |rng| {
+                (0..rng.gen_range(0, 20))
+                    .map(|_| rng.gen_range(0, 10))
+                    .collect::<Vec<usize>>()
+            },
+            |input| {
+                cases += 1;
+                if input.iter().all(|n| *n < 10) {
+                    Ok(())
+                } else {
+                    Err("out of range".to_owned())
+                }
+            },
+        );
+        assert_eq!(cases, Config::default().cases);
+    }
+
+    #[test]
+    #[should_panic(expected = "shrunk in")]
+    fn failing_properties_panic_with_the_shrunk_input() {
+        check(
+            &Config::default(),
+            |rng| rng.gen_range(100, 1000),
+            |n: &usize| {
+                if *n < 50 {
+                    Ok(())
+                } else {
+                    Err("too large".to_owned())
+                }
+            },
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! A `#[global_allocator]` wrapper that records the allocations of a program into a trace, which
+//! `bench-alloc replay` can replay through other allocators.
+//!
+//! ```no_run
+//! use bench_alloc::recorder::{self, Recorder};
+//! use std::alloc::System;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: Recorder<System> = Recorder::new(System);
+//!
+//! fn main() {
+//!     recorder::start("program.trace").expect("Failed to start recording");
+//!     // Everything that is allocated from here on is recorded, until the program exits or
+//!     // `recorder::stop` is called.
+//! }
+//! ```
+//!
+//! Events are encoded into a fixed buffer, which is written to the trace whenever it fills up,
+//! so recording never allocates itself. Objects are identified by their address. Reallocations
+//! are serialized with the recording of other events, so that an address that they free cannot
+//! be recorded as allocated by another thread first.
+
+use crate::trace::{self, Event, Operation, MAX_EVENT_LEN};
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::raw::c_int;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+const BUFFER_LEN: usize = 64 * 1024;
+
+struct State {
+    buffer: [u8; BUFFER_LEN],
+    len: usize,
+    file: Option<File>,
+}
+
+impl State {
+    fn push(&mut self, event: &Event) {
+        if self.len + MAX_EVENT_LEN > BUFFER_LEN {
+            // Recording has no way to report errors; a failed write truncates the trace.
+            let _ = self.flush();
+        }
+        let mut encoded = [0; MAX_EVENT_LEN];
+        let len = event.encode(&mut encoded);
+        self.buffer[self.len..self.len + len].copy_from_slice(&encoded[..len]);
+        self.len += len;
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let len = self.len;
+        self.len = 0;
+        match &mut self.file {
+            Some(file) => file.write_all(&self.buffer[..len]),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The recording state, which is shared by all threads and protected by a spin lock. A spin
+/// lock is used, because other locks may allocate.
+struct Shared {
+    locked: AtomicBool,
+    state: UnsafeCell<State>,
+}
+
+unsafe impl Sync for Shared {}
+
+static SHARED: Shared = Shared {
+    locked: AtomicBool::new(false),
+    state: UnsafeCell::new(State {
+        buffer: [0; BUFFER_LEN],
+        len: 0,
+        file: None,
+    }),
+};
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static AT_EXIT_REGISTERED: AtomicBool = AtomicBool::new(false);
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// A small id for the current thread, assigned on its first recorded event.
+    static THREAD_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+extern "C" fn stop_at_exit() {
+    let _ = stop();
+}
+
+struct Guard;
+
+impl Guard {
+    fn lock() -> Self {
+        while SHARED
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            thread::yield_now();
+        }
+        Guard
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn state(&self) -> &mut State {
+        unsafe { &mut *SHARED.state.get() }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        SHARED.locked.store(false, Ordering::Release);
+    }
+}
+
+fn thread_id() -> Option<u64> {
+    // The thread-local is unavailable while the thread is being torn down.
+    THREAD_ID
+        .try_with(|id| {
+            if id.get() == 0 {
+                id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+            }
+            id.get()
+        })
+        .ok()
+}
+
+fn record(guard: &Guard, id: *mut u8, operation: Operation) {
+    guard.state().push(&Event {
+        id: id as u64,
+        thread: thread_id(),
+        operation,
+    });
+}
+
+/// Starts recording all allocations into a new trace at `path`. The trace is written when
+/// `stop` is called or the program exits.
+pub fn start<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    // Opening the file allocates, so it has to happen before recording is enabled.
+    let mut file = File::create(path)?;
+    trace::write_header(&mut file)?;
+
+    {
+        let guard = Guard::lock();
+        let state = guard.state();
+        state.flush()?;
+        state.file = Some(file);
+    }
+    if !AT_EXIT_REGISTERED.swap(true, Ordering::SeqCst) {
+        unsafe { atexit(stop_at_exit) };
+    }
+    RECORDING.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Stops recording, and writes and closes the trace.
+pub fn stop() -> io::Result<()> {
+    RECORDING.store(false, Ordering::SeqCst);
+    let guard = Guard::lock();
+    let state = guard.state();
+    let result = state.flush();
+    state.file = None;
+    result
+}
+
+/// Records every allocation that is served by `A` while recording is started.
+pub struct Recorder<A> {
+    inner: A,
+}
+
+impl<A> Recorder<A> {
+    pub const fn new(inner: A) -> Self {
+        Recorder { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Recorder<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() && RECORDING.load(Ordering::Relaxed) {
+            let operation = Operation::Alloc {
+                size: layout.size(),
+                align: layout.align(),
+            };
+            record(&Guard::lock(), ptr, operation);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() && RECORDING.load(Ordering::Relaxed) {
+            let operation = Operation::Alloc {
+                size: layout.size(),
+                align: layout.align(),
+            };
+            record(&Guard::lock(), ptr, operation);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Record before releasing, so that another thread cannot record reusing the address
+        // first.
+        if RECORDING.load(Ordering::Relaxed) {
+            record(&Guard::lock(), ptr, Operation::Dealloc);
+        }
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !RECORDING.load(Ordering::Relaxed) {
+            return self.inner.realloc(ptr, layout, new_size);
+        }
+
+        let guard = Guard::lock();
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let new_id = if new_ptr == ptr {
+                None
+            } else {
+                Some(new_ptr as u64)
+            };
+            let operation = Operation::Realloc {
+                size: new_size,
+                new_id,
+            };
+            record(&guard, ptr, operation);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{start, stop, thread_id, Recorder, BUFFER_LEN};
+    use crate::trace::{Event, Operation, TraceReader};
+    use std::alloc::{self, Layout, System};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    // The tests of the library run on the recorder, so that anything it allocates itself while
+    // recording would show up in the trace, or deadlock on its lock.
+    #[global_allocator]
+    static ALLOCATOR: Recorder<System> = Recorder::new(System);
+
+    /// Recording is global, so only one test may record at a time.
+    static RECORDING: Mutex<()> = Mutex::new(());
+
+    /// Records what `f` allocates, and returns the events of the current thread. Other threads
+    /// of the test harness may allocate while recording.
+    fn record<F: FnOnce()>(name: &str, f: F) -> Vec<Event> {
+        let _recording = RECORDING.lock().unwrap_or_else(|error| error.into_inner());
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("bench-alloc-{}-{}.trace", name, std::process::id()));
+        let thread = thread_id();
+
+        start(&path).unwrap();
+        f();
+        stop().unwrap();
+
+        let events: Vec<Event> = TraceReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        events
+            .into_iter()
+            .filter(|event| event.thread == thread)
+            .collect()
+    }
+
+    #[test]
+    fn allocations_are_recorded_without_those_of_the_recorder() {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let mut pointers = [0; 4];
+        let events = record("calls", || unsafe {
+            let a = alloc::alloc(layout);
+            let b = alloc::alloc_zeroed(Layout::from_size_align(100, 64).unwrap());
+            let moved = alloc::realloc(a, layout, 4096);
+            alloc::dealloc(b, Layout::from_size_align(100, 64).unwrap());
+            alloc::dealloc(moved, Layout::from_size_align(4096, 8).unwrap());
+            pointers = [a as usize, b as usize, moved as usize, 0];
+        });
+
+        let [a, b, moved, _] = pointers;
+        let new_id = if moved == a { None } else { Some(moved as u64) };
+        let operations: Vec<(u64, Operation)> = events
+            .iter()
+            .map(|event| (event.id, event.operation))
+            .collect();
+        // Creating and writing the trace in `start` and `stop` is not recorded.
+        assert_eq!(
+            operations,
+            vec![
+                (a as u64, Operation::Alloc { size: 24, align: 8 }),
+                (
+                    b as u64,
+                    Operation::Alloc {
+                        size: 100,
+                        align: 64
+                    }
+                ),
+                (a as u64, Operation::Realloc { size: 4096, new_id }),
+                (b as u64, Operation::Dealloc),
+                (moved as u64, Operation::Dealloc),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_full_buffer_is_written_without_losing_events() {
+        // Every pair of events takes at least 8 bytes, so the buffer fills up several times.
+        let pairs = 4 * BUFFER_LEN / 8;
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let events = record("overflow", || {
+            for _ in 0..pairs {
+                unsafe { alloc::dealloc(alloc::alloc(layout), layout) };
+            }
+        });
+
+        assert_eq!(events.len(), 2 * pairs);
+        for pair in events.chunks(2) {
+            assert_eq!(pair[0].operation, Operation::Alloc { size: 8, align: 8 });
+            assert_eq!(pair[1].operation, Operation::Dealloc);
+            assert_eq!(pair[0].id, pair[1].id);
+        }
+    }
+}
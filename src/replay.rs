@@ -0,0 +1,217 @@
+use crate::clock;
+use crate::AllocRefV2;
+use bench_alloc::trace::{Event, Operation};
+use std::alloc::Layout;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ptr::NonNull;
+use std::time::Duration;
+
+/// An operation of a prepared trace, on a slot instead of an object id.
+#[derive(Clone, Copy)]
+enum Op {
+    Alloc { slot: usize, layout: Layout },
+    Dealloc { slot: usize },
+    Realloc { slot: usize, new_layout: Layout },
+}
+
+/// Statistics of a trace, which do not depend on the allocator that replays it.
+#[derive(Clone, Debug, Default)]
+pub struct TraceStats {
+    pub allocs: usize,
+    pub deallocs: usize,
+    pub reallocs: usize,
+    /// Events on objects that the trace never allocated, e.g. because they were allocated before
+    /// recording started. They are skipped.
+    pub skipped: usize,
+    pub threads: usize,
+    /// The largest number of bytes that are requested at the same time.
+    pub peak_bytes: usize,
+    /// The largest number of objects that are live at the same time.
+    pub peak_objects: usize,
+    /// The number of bytes requested over the whole trace, counting every reallocation.
+    pub total_bytes: usize,
+    /// The largest size and alignment of any object.
+    pub max_size: usize,
+    pub max_align: usize,
+}
+
+impl fmt::Display for TraceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "events:   {} allocs, {} deallocs, {} reallocs from {} thread(s)",
+            self.allocs, self.deallocs, self.reallocs, self.threads
+        )?;
+        if self.skipped > 0 {
+            writeln!(
+                f,
+                "skipped:  {} events on objects that were allocated before the trace",
+                self.skipped
+            )?;
+        }
+        write!(
+            f,
+            "peak:     {} bytes in {} live objects; {} bytes requested in total",
+            self.peak_bytes, self.peak_objects, self.total_bytes
+        )
+    }
+}
+
+/// A trace prepared for replaying. Object ids are mapped to dense slots up front, so replaying
+/// does not spend time on looking them up.
+pub struct Replay {
+    ops: Vec<Op>,
+    slots: usize,
+    pub stats: TraceStats,
+}
+
+impl Replay {
+    /// Prepares the events of a trace. Thread ids are only counted: all events are replayed on
+    /// the current thread, in order.
+    pub fn new<I: IntoIterator<Item = Event>>(events: I) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        let mut stats = TraceStats::default();
+        let mut threads = HashSet::new();
+        // Maps the ids of live objects to their slot and layout.
+        let mut live: HashMap<u64, (usize, Layout)> = HashMap::new();
+        let mut free_slots = Vec::new();
+        let mut slots = 0;
+        let mut live_bytes: usize = 0;
+
+        for (index, event) in events.into_iter().enumerate() {
+            let overflow = || format!("Event {} allocates more bytes than fit in memory.", index);
+            threads.insert(event.thread);
+            match event.operation {
+                Operation::Alloc { size, align } => {
+                    let layout = Layout::from_size_align(size, align)
+                        .map_err(|_| format!("Event {} has an invalid layout.", index))?;
+                    let slot = free_slots.pop().unwrap_or_else(|| {
+                        slots += 1;
+                        slots - 1
+                    });
+                    if live.insert(event.id, (slot, layout)).is_some() {
+                        return Err(format!(
+                            "Event {} allocates object {}, which is still live.",
+                            index, event.id
+                        ));
+                    }
+                    ops.push(Op::Alloc { slot, layout });
+                    stats.allocs += 1;
+                    stats.max_size = stats.max_size.max(size);
+                    stats.max_align = stats.max_align.max(align);
+                    live_bytes = live_bytes.checked_add(size).ok_or_else(overflow)?;
+                    stats.total_bytes = stats.total_bytes.checked_add(size).ok_or_else(overflow)?;
+                }
+                Operation::Dealloc => {
+                    let (slot, layout) = match live.remove(&event.id) {
+                        Some(object) => object,
+                        None => {
+                            stats.skipped += 1;
+                            continue;
+                        }
+                    };
+                    free_slots.push(slot);
+                    ops.push(Op::Dealloc { slot });
+                    stats.deallocs += 1;
+                    live_bytes -= layout.size();
+                }
+                Operation::Realloc { size, new_id } => {
+                    let (slot, layout) = match live.remove(&event.id) {
+                        Some(object) => object,
+                        None => {
+                            stats.skipped += 1;
+                            continue;
+                        }
+                    };
+                    let new_layout = Layout::from_size_align(size, layout.align())
+                        .map_err(|_| format!("Event {} has an invalid layout.", index))?;
+                    let id = new_id.unwrap_or(event.id);
+                    if live.insert(id, (slot, new_layout)).is_some() {
+                        return Err(format!(
+                            "Event {} moves an object to {}, which is still live.",
+                            index, id
+                        ));
+                    }
+                    ops.push(Op::Realloc { slot, new_layout });
+                    stats.reallocs += 1;
+                    stats.max_size = stats.max_size.max(size);
+                    live_bytes = (live_bytes - layout.size())
+                        .checked_add(size)
+                        .ok_or_else(overflow)?;
+                    stats.total_bytes = stats.total_bytes.checked_add(size).ok_or_else(overflow)?;
+                }
+            }
+            stats.peak_bytes = stats.peak_bytes.max(live_bytes);
+            stats.peak_objects = stats.peak_objects.max(live.len());
+        }
+        stats.threads = threads.len();
+
+        Ok(Replay { ops, slots, stats })
+    }
+
+    /// The layouts of every allocation and reallocation, in order.
+    pub fn layouts(&self) -> impl Iterator<Item = Layout> + '_ {
+        self.ops.iter().filter_map(|op| match *op {
+            Op::Alloc { layout, .. }
+            | Op::Realloc {
+                new_layout: layout, ..
+            } => Some(layout),
+            Op::Dealloc { .. } => None,
+        })
+    }
+}
+
+/// The result of replaying a trace once.
+pub struct ReplayRun {
+    pub elapsed: Duration,
+    /// The number of allocations and reallocations that failed. Later events on the objects of
+    /// failed allocations are skipped.
+    pub failures: usize,
+}
+
+/// Replays `replay` through `a`. Objects that the trace leaves live are released afterwards,
+/// outside of the timed region.
+pub fn run<A: AllocRefV2>(a: A, replay: &Replay) -> ReplayRun {
+    let mut live: Vec<Option<(NonNull<u8>, Layout)>> = vec![None; replay.slots];
+    let mut failures = 0;
+
+    let before = clock::now();
+    for op in &replay.ops {
+        match *op {
+            Op::Alloc { slot, layout } => match a.alloc(layout) {
+                Ok(ptr) => live[slot] = Some((ptr, layout)),
+                Err(_) => failures += 1,
+            },
+            Op::Dealloc { slot } => {
+                if let Some((ptr, layout)) = live[slot].take() {
+                    unsafe { a.dealloc(ptr, layout) };
+                }
+            }
+            Op::Realloc { slot, new_layout } => {
+                if let Some((ptr, layout)) = live[slot] {
+                    let size = new_layout.size();
+                    let result = if size > layout.size() {
+                        unsafe { a.grow(ptr, layout, size) }
+                    } else if size < layout.size() {
+                        unsafe { a.shrink(ptr, layout, size) }
+                    } else {
+                        Ok(ptr)
+                    };
+                    // A failed reallocation leaves the original allocation intact.
+                    match result {
+                        Ok(new_ptr) => live[slot] = Some((new_ptr, new_layout)),
+                        Err(_) => failures += 1,
+                    }
+                }
+            }
+        }
+    }
+    let elapsed = before.elapsed();
+
+    for (ptr, layout) in live.into_iter().flatten() {
+        unsafe { a.dealloc(ptr, layout) };
+    }
+
+    ReplayRun { elapsed, failures }
+}
@@ -0,0 +1,326 @@
+use crate::distribution::{AlignDistribution, SizeDistribution};
+use crate::failing::Failure;
+use std::fmt;
+
+/// A closed set of named options that can be selected on the command line.
+pub trait Choice: Copy + Sized + 'static {
+    /// What the option selects, used in error messages.
+    const WHAT: &'static str;
+    const ALL: &'static [Self];
+
+    fn name(self) -> &'static str;
+
+    fn description(self) -> &'static str;
+
+    fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|choice| choice.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|choice| choice.name()).collect();
+                format!(
+                    "Unknown {} '{}'; expected one of: {}.",
+                    Self::WHAT,
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The allocator that serves the measured operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Allocator {
+    Bump,
+    BumpNew,
+    BumpUndersized,
+    BumpReset,
+    Global,
+    System,
+    Malloc,
+    PosixMemalign,
+    AlignedAlloc,
+    FreeList,
+    Slab,
+    Pool,
+    Linear,
+}
+
+impl Choice for Allocator {
+    const WHAT: &'static str = "allocator";
+    const ALL: &'static [Self] = &[
+        Allocator::Bump,
+        Allocator::BumpNew,
+        Allocator::BumpUndersized,
+        Allocator::BumpReset,
+        Allocator::Global,
+        Allocator::System,
+        Allocator::Malloc,
+        Allocator::PosixMemalign,
+        Allocator::AlignedAlloc,
+        Allocator::FreeList,
+        Allocator::Slab,
+        Allocator::Pool,
+        Allocator::Linear,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Allocator::Bump => "bump",
+            Allocator::BumpNew => "bump-new",
+            Allocator::BumpUndersized => "bump-undersized",
+            Allocator::BumpReset => "bump-reset",
+            Allocator::Global => "global",
+            Allocator::System => "system",
+            Allocator::Malloc => "malloc",
+            Allocator::PosixMemalign => "posix-memalign",
+            Allocator::AlignedAlloc => "aligned-alloc",
+            Allocator::FreeList => "free-list",
+            Allocator::Slab => "slab",
+            Allocator::Pool => "pool",
+            Allocator::Linear => "linear",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Allocator::Bump => "bumpalo arena, pre-sized to 1 KiB per iteration",
+            Allocator::BumpNew => "bumpalo arena from `Bump::new()`, growing chunk by chunk",
+            Allocator::BumpUndersized => {
+                "bumpalo arena pre-sized to 1/8 of the workload, then growing"
+            }
+            Allocator::BumpReset => "one bumpalo arena from `Bump::new()`, reset for every sample",
+            Allocator::Global => "alloc-wg's Global allocator",
+            Allocator::System => "std::alloc::System, bypassing alloc-wg",
+            Allocator::Malloc => {
+                "libc malloc and realloc, or posix_memalign for large alignments, via FFI"
+            }
+            Allocator::PosixMemalign => {
+                "libc posix_memalign for every allocation, resizing by copying, via FFI"
+            }
+            Allocator::AlignedAlloc => {
+                "C11 aligned_alloc for every allocation, resizing by copying, via FFI"
+            }
+            Allocator::FreeList => "first-fit free list over an arena sized for the workload",
+            Allocator::Slab => "power-of-two size classes carved from 64 KiB slabs",
+            Allocator::Pool => "fixed pool of objects that fit the largest layout",
+            Allocator::Linear => "linear allocator over a preallocated buffer, reset per sample",
+        }
+    }
+}
+
+/// The distribution of allocation sizes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sizes {
+    Zero,
+    NonZero,
+    Mixed,
+}
+
+impl Choice for Sizes {
+    const WHAT: &'static str = "size distribution";
+    const ALL: &'static [Self] = &[Sizes::Zero, Sizes::NonZero, Sizes::Mixed];
+
+    fn name(self) -> &'static str {
+        match self {
+            Sizes::Zero => "zero",
+            Sizes::NonZero => "nonzero",
+            Sizes::Mixed => "mixed",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Sizes::Zero => "only zero-sized allocations",
+            Sizes::NonZero => "non-zero sizes drawn from --size-dist",
+            Sizes::Mixed => "zero sizes at --zst-ratio, interleaved by --pattern",
+        }
+    }
+}
+
+/// How zero-sized layouts are interleaved with non-zero-sized ones in a mixed workload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    Random,
+    Periodic,
+    Bursty,
+}
+
+impl Choice for Pattern {
+    const WHAT: &'static str = "pattern";
+    const ALL: &'static [Self] = &[Pattern::Random, Pattern::Periodic, Pattern::Bursty];
+
+    fn name(self) -> &'static str {
+        match self {
+            Pattern::Random => "random",
+            Pattern::Periodic => "periodic",
+            Pattern::Bursty => "bursty",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Pattern::Random => "every layout is zero-sized independently of the others",
+            Pattern::Periodic => "zero-sized layouts are evenly spaced",
+            Pattern::Bursty => "runs of each kind, where the rarer kind averages --burst layouts",
+        }
+    }
+}
+
+/// How the `AllocRefV2` methods are called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Call {
+    Branched,
+    Direct,
+}
+
+impl Choice for Call {
+    const WHAT: &'static str = "call style";
+    const ALL: &'static [Self] = &[Call::Branched, Call::Direct];
+
+    fn name(self) -> &'static str {
+        match self {
+            Call::Branched => "branched",
+            Call::Direct => "direct",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Call::Branched => "call e.g. `alloc`, which branches on the size",
+            Call::Direct => "call e.g. `alloc_zst` or `alloc_non_zst` directly, as an oracle would",
+        }
+    }
+}
+
+/// The operations whose cost is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Only time allocations; memory is released afterwards.
+    Alloc,
+    /// Only time zeroed allocations through `alloc_zeroed`.
+    AllocZeroed,
+    /// Only time allocations that are zeroed manually with a memset afterwards.
+    AllocMemset,
+    /// Only time deallocations; memory is allocated beforehand.
+    Dealloc,
+    /// Time allocations and their subsequent deallocations together.
+    AllocDealloc,
+    /// Only time growing allocations; with zero-sized layouts this grows from zero.
+    Grow,
+    /// Only time shrinking allocations; with zero-sized layouts this shrinks to zero.
+    Shrink,
+}
+
+impl Choice for Mode {
+    const WHAT: &'static str = "mode";
+    const ALL: &'static [Self] = &[
+        Mode::Alloc,
+        Mode::AllocZeroed,
+        Mode::AllocMemset,
+        Mode::Dealloc,
+        Mode::AllocDealloc,
+        Mode::Grow,
+        Mode::Shrink,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Alloc => "alloc",
+            Mode::AllocZeroed => "alloc-zeroed",
+            Mode::AllocMemset => "alloc-memset",
+            Mode::Dealloc => "dealloc",
+            Mode::AllocDealloc => "alloc-dealloc",
+            Mode::Grow => "grow",
+            Mode::Shrink => "shrink",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Mode::Alloc => "time allocations",
+            Mode::AllocZeroed => "time zeroed allocations through `alloc_zeroed`",
+            Mode::AllocMemset => "time allocations followed by a manual memset",
+            Mode::Dealloc => "time deallocations",
+            Mode::AllocDealloc => "time allocations and deallocations together",
+            Mode::Grow => "time growing allocations by 1..=1024 bytes",
+            Mode::Shrink => "time shrinking allocations by 1..=1024 bytes",
+        }
+    }
+}
+
+/// A single benchmark configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub iters: usize,
+    /// Seed of the random number generator that generates the layouts.
+    pub seed: u64,
+    pub size_dist: SizeDistribution,
+    pub align_dist: AlignDistribution,
+    /// The fraction of zero-sized layouts in a mixed workload.
+    pub zst_ratio: f64,
+    pub pattern: Pattern,
+    /// The mean length of a run of the rarer kind of layout in a bursty workload.
+    pub burst: usize,
+    pub allocator: Allocator,
+    pub sizes: Sizes,
+    pub call: Call,
+    pub mode: Mode,
+    /// The calls that fail on purpose, to measure the error paths.
+    pub failure: Option<Failure>,
+}
+
+impl Scenario {
+    pub fn is_direct(&self) -> bool {
+        self.call == Call::Direct
+    }
+
+    /// Checks whether the combination of options can be run.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iters == 0 {
+            return Err("The number of iterations must be greater than zero.".to_owned());
+        }
+        if !(self.zst_ratio >= 0.0 && self.zst_ratio <= 1.0) {
+            return Err("The ratio of zero-sized layouts must be between 0 and 1.".to_owned());
+        }
+        if self.burst == 0 {
+            return Err("The burst length must be greater than zero.".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Checks the calls that failed in a sample. Without a failure policy every call must
+    /// succeed: a failure means that the allocator ran out of memory or rejected a layout, so the
+    /// samples did not time the work that they claim to.
+    pub fn check_failures(&self, failures: usize) -> Result<(), String> {
+        if failures > 0 && self.failure.is_none() {
+            return Err(format!(
+                "{} calls failed in a sample of '{}' without a failure policy; the allocator \
+                 ran out of memory or rejected a layout.",
+                failures, self
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.allocator.name(), self.sizes.name())?;
+        if self.sizes == Sizes::Mixed {
+            write!(f, ":{}", self.pattern.name())?;
+        }
+        write!(
+            f,
+            " {} {} x{}",
+            self.call.name(),
+            self.mode.name(),
+            self.iters
+        )?;
+        if let Some(failure) = &self.failure {
+            write!(f, " failing {}", failure)?;
+        }
+        Ok(())
+    }
+}
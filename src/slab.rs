@@ -0,0 +1,219 @@
+//! A segregated size-class slab allocator.
+
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::ptr::{self, NonNull};
+
+/// The smallest size class, which has room for the link of a free slot.
+const MIN_CLASS: usize = 16;
+/// The number of size classes: 16, 32, ..., 4096 bytes.
+const NUM_CLASSES: usize = 9;
+/// Slabs are aligned to their size, so every slot is aligned to its size class.
+const SLAB_SIZE: usize = 64 * 1024;
+
+/// A free slot, linked to the next free slot of its size class.
+struct FreeSlot {
+    next: *mut FreeSlot,
+}
+
+/// Serves every allocation from the free slots of the smallest power-of-two size class that
+/// fits both its size and alignment. Slabs are carved into slots of a single class when that
+/// class runs out. Allocations larger than the largest class go to `System` directly.
+pub struct Slab {
+    classes: [Cell<*mut FreeSlot>; NUM_CLASSES],
+    slabs: RefCell<Vec<NonNull<u8>>>,
+}
+
+/// Returns the size class that serves `layout`, or `None` if it is too large for any class.
+fn class_of(layout: Layout) -> Option<usize> {
+    let size = cmp::max(cmp::max(layout.size(), layout.align()), MIN_CLASS);
+    let class = size.next_power_of_two().trailing_zeros() - MIN_CLASS.trailing_zeros();
+    if (class as usize) < NUM_CLASSES {
+        Some(class as usize)
+    } else {
+        None
+    }
+}
+
+fn slab_layout() -> Layout {
+    Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+}
+
+impl Slab {
+    pub fn new() -> Self {
+        Slab {
+            classes: Default::default(),
+            slabs: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The number of slabs that were carved so far.
+    #[cfg(test)]
+    fn slabs(&self) -> usize {
+        self.slabs.borrow().len()
+    }
+
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let class = match class_of(layout) {
+            Some(class) => class,
+            None => {
+                return NonNull::new(unsafe { GlobalAlloc::alloc(&System, layout) }).ok_or(AllocErr)
+            }
+        };
+
+        let mut slot = self.classes[class].get();
+        if slot.is_null() {
+            slot = self.refill(class)?;
+        }
+        self.classes[class].set(unsafe { (*slot).next });
+        Ok(unsafe { NonNull::new_unchecked(slot as *mut u8) })
+    }
+
+    /// Carves a new slab into free slots of `class`, and returns the first one.
+    fn refill(&self, class: usize) -> Result<*mut FreeSlot, AllocErr> {
+        let slab =
+            NonNull::new(unsafe { GlobalAlloc::alloc(&System, slab_layout()) }).ok_or(AllocErr)?;
+        self.slabs.borrow_mut().push(slab);
+
+        let slot_size = MIN_CLASS << class;
+        let mut next = ptr::null_mut();
+        for offset in (0..SLAB_SIZE).step_by(slot_size).rev() {
+            let slot = unsafe { slab.as_ptr().add(offset) } as *mut FreeSlot;
+            unsafe { slot.write(FreeSlot { next }) };
+            next = slot;
+        }
+        Ok(next)
+    }
+
+    unsafe fn release(&self, ptr: NonNull<u8>, layout: Layout) {
+        match class_of(layout) {
+            Some(class) => {
+                let slot = ptr.as_ptr() as *mut FreeSlot;
+                slot.write(FreeSlot {
+                    next: self.classes[class].get(),
+                });
+                self.classes[class].set(slot);
+            }
+            None => GlobalAlloc::dealloc(&System, ptr.as_ptr(), layout),
+        }
+    }
+
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        match (class_of(layout), class_of(new_layout)) {
+            (Some(class), Some(new_class)) if class == new_class => Ok(ptr),
+            (None, None) => NonNull::new(GlobalAlloc::realloc(
+                &System,
+                ptr.as_ptr(),
+                layout,
+                new_layout.size(),
+            ))
+            .ok_or(AllocErr),
+            _ => {
+                let new_ptr = self.allocate(new_layout)?;
+                let size = cmp::min(layout.size(), new_layout.size());
+                ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), size);
+                self.release(ptr, layout);
+                Ok(new_ptr)
+            }
+        }
+    }
+}
+
+impl Default for Slab {
+    fn default() -> Self {
+        Slab::new()
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        for slab in self.slabs.get_mut().drain(..) {
+            unsafe { GlobalAlloc::dealloc(&System, slab.as_ptr(), slab_layout()) };
+        }
+    }
+}
+
+impl AllocRef for &Slab {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        self.allocate(layout.into())
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let layout = Layout::from(layout);
+        let ptr = self.allocate(layout)?;
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+        Ok(ptr)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.release(ptr, layout.into())
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        self.resize(ptr, layout.into(), new_layout.into())
+    }
+}
+
+crate::impl_alloc_ref_v2!(AllocRef for &Slab);
+
+#[cfg(test)]
+mod tests {
+    use super::{Slab, SLAB_SIZE};
+    use crate::conformance::layout;
+    use crate::AllocRefV2;
+    use std::ptr;
+
+    #[test]
+    fn released_slots_are_reused() {
+        let slab = Slab::new();
+        let a = slab.alloc(layout(24, 8)).unwrap();
+        unsafe { slab.dealloc(a, layout(24, 8)) };
+        // 24 and 32 bytes share a size class.
+        let b = slab.alloc(layout(32, 8)).unwrap();
+        assert_eq!(a, b);
+        unsafe { slab.dealloc(b, layout(32, 8)) };
+        assert_eq!(slab.slabs(), 1);
+    }
+
+    #[test]
+    fn a_class_is_refilled_when_it_runs_out() {
+        let slab = Slab::new();
+        let count = SLAB_SIZE / 64 + 1;
+        let ptrs: Vec<_> = (0..count)
+            .map(|_| slab.alloc(layout(64, 64)).unwrap())
+            .collect();
+        assert_eq!(slab.slabs(), 2);
+        for ptr in ptrs {
+            unsafe { slab.dealloc(ptr, layout(64, 64)) };
+        }
+    }
+
+    #[test]
+    fn grow_and_shrink_keep_contents() {
+        let slab = Slab::new();
+        let ptr = slab.alloc(layout(20, 4)).unwrap();
+        unsafe {
+            ptr::write_bytes(ptr.as_ptr(), 0xCD, 20);
+            // Within the class of 32 bytes, the allocation stays in place.
+            let same = slab.grow(ptr, layout(20, 4), 32).unwrap();
+            assert_eq!(same, ptr);
+            let grown = slab.grow(same, layout(32, 4), 10_000).unwrap();
+            assert!((0..20).all(|i| *grown.as_ptr().add(i) == 0xCD));
+            let shrunk = slab.shrink(grown, layout(10_000, 4), 8).unwrap();
+            assert!((0..8).all(|i| *shrunk.as_ptr().add(i) == 0xCD));
+            slab.dealloc(shrunk, layout(8, 4));
+        }
+    }
+}
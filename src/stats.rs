@@ -0,0 +1,506 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+/// The fraction of the baseline below which a baseline-corrected mean is suspicious: the
+/// measured loop then barely did more than the empty one.
+const NEAR_BASELINE: f64 = 0.1;
+
+/// Controls how many times a scenario is run and how its samples are analysed.
+#[derive(Clone, Copy, Debug)]
+pub struct MeasureConfig {
+    /// Number of untimed runs before sampling starts, to warm up caches and the allocator.
+    pub warmup: usize,
+    /// Number of timed runs.
+    pub samples: usize,
+    /// Number of bootstrap resamples used to estimate the confidence interval.
+    pub resamples: usize,
+    /// Confidence level of the reported interval, e.g. `0.95`.
+    pub confidence: f64,
+    /// Seed of the bootstrap resampling, so that a replayed run reports the same interval.
+    pub seed: u64,
+}
+
+impl Default for MeasureConfig {
+    fn default() -> Self {
+        MeasureConfig {
+            warmup: 3,
+            samples: 30,
+            resamples: 10_000,
+            confidence: 0.95,
+            seed: 0,
+        }
+    }
+}
+
+/// Outlier counts according to Tukey's fences: samples beyond 1.5 IQR of the quartiles are
+/// mild outliers, samples beyond 3 IQR are severe outliers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Outliers {
+    pub low_severe: usize,
+    pub low_mild: usize,
+    pub high_mild: usize,
+    pub high_severe: usize,
+}
+
+impl Outliers {
+    fn classify(sorted: &[f64]) -> Self {
+        let q1 = percentile(sorted, 0.25);
+        let q3 = percentile(sorted, 0.75);
+        let iqr = q3 - q1;
+        let (low_severe, low_mild) = (q1 - 3.0 * iqr, q1 - 1.5 * iqr);
+        let (high_mild, high_severe) = (q3 + 1.5 * iqr, q3 + 3.0 * iqr);
+
+        let mut outliers = Outliers::default();
+        for &x in sorted {
+            if x < low_severe {
+                outliers.low_severe += 1;
+            } else if x < low_mild {
+                outliers.low_mild += 1;
+            } else if x > high_severe {
+                outliers.high_severe += 1;
+            } else if x > high_mild {
+                outliers.high_mild += 1;
+            }
+        }
+        outliers
+    }
+
+    pub fn total(&self) -> usize {
+        self.low_severe + self.low_mild + self.high_mild + self.high_severe
+    }
+}
+
+/// Descriptive statistics over the samples of a scenario, in nanoseconds.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub samples: usize,
+    /// The number of operations that every sample timed.
+    pub operations: usize,
+    /// The most calls that failed in any run of a sample.
+    pub failures: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Bootstrap confidence interval of the mean.
+    pub ci: (f64, f64),
+    pub confidence: f64,
+    pub outliers: Outliers,
+    /// The median of the empty-loop baseline, which was subtracted from every sample, or zero
+    /// if none was.
+    pub baseline: f64,
+}
+
+impl Summary {
+    pub fn new(samples: &[f64], operations: usize, config: &MeasureConfig) -> Self {
+        assert!(!samples.is_empty(), "Cannot summarise zero samples");
+
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        Summary {
+            samples: samples.len(),
+            operations,
+            failures: 0,
+            mean: mean(samples),
+            median: percentile(&sorted, 0.5),
+            std_dev: std_dev(samples),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            ci: bootstrap_mean_ci(
+                samples,
+                config.resamples,
+                config.confidence,
+                &mut ChaCha8Rng::seed_from_u64(config.seed),
+            ),
+            confidence: config.confidence,
+            outliers: Outliers::classify(&sorted),
+            baseline: 0.0,
+        }
+    }
+
+    /// The share of a single operation in a duration of a sample.
+    pub fn per_operation(&self, nanos: f64) -> f64 {
+        nanos / self.operations.max(1) as f64
+    }
+
+    /// Whether the samples, after subtracting the baseline, are so close to it that the
+    /// measured work may have been optimized away.
+    pub fn near_baseline(&self) -> bool {
+        self.baseline > 0.0 && self.mean < self.baseline * NEAR_BASELINE
+    }
+
+    /// Serialises the summary into a single whitespace-separated line, so it can be passed
+    /// between processes.
+    pub fn to_record(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            self.samples,
+            self.operations,
+            self.failures,
+            self.mean,
+            self.median,
+            self.std_dev,
+            self.min,
+            self.max,
+            self.ci.0,
+            self.ci.1,
+            self.confidence,
+            self.outliers.low_severe,
+            self.outliers.low_mild,
+            self.outliers.high_mild,
+            self.outliers.high_severe,
+            self.baseline
+        )
+    }
+
+    /// Parses a line produced by `to_record`.
+    pub fn from_record(record: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = record.split_whitespace().collect();
+        if fields.len() != 16 {
+            return Err(format!("Malformed summary record '{}'.", record));
+        }
+
+        let float = |i: usize| -> Result<f64, String> {
+            fields[i]
+                .parse()
+                .map_err(|_| format!("Malformed number '{}' in summary record.", fields[i]))
+        };
+        let count = |i: usize| -> Result<usize, String> {
+            fields[i]
+                .parse()
+                .map_err(|_| format!("Malformed count '{}' in summary record.", fields[i]))
+        };
+
+        Ok(Summary {
+            samples: count(0)?,
+            operations: count(1)?,
+            failures: count(2)?,
+            mean: float(3)?,
+            median: float(4)?,
+            std_dev: float(5)?,
+            min: float(6)?,
+            max: float(7)?,
+            ci: (float(8)?, float(9)?),
+            confidence: float(10)?,
+            outliers: Outliers {
+                low_severe: count(11)?,
+                low_mild: count(12)?,
+                high_mild: count(13)?,
+                high_severe: count(14)?,
+            },
+            baseline: float(15)?,
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "samples:  {}", self.samples)?;
+        if self.failures > 0 {
+            writeln!(f, "failures: {} calls per sample", self.failures)?;
+        }
+        writeln!(
+            f,
+            "mean:     {:.3} us [{:.3} us, {:.3} us] ({}% CI)",
+            self.mean / 1e3,
+            self.ci.0 / 1e3,
+            self.ci.1 / 1e3,
+            self.confidence * 100.0
+        )?;
+        writeln!(f, "median:   {:.3} us", self.median / 1e3)?;
+        writeln!(
+            f,
+            "per op:   {:.3} ns mean [{:.3} ns, {:.3} ns], {:.3} ns median over {} operations",
+            self.per_operation(self.mean),
+            self.per_operation(self.ci.0),
+            self.per_operation(self.ci.1),
+            self.per_operation(self.median),
+            self.operations
+        )?;
+        writeln!(f, "std dev:  {:.3} us", self.std_dev / 1e3)?;
+        writeln!(
+            f,
+            "range:    {:.3} us .. {:.3} us",
+            self.min / 1e3,
+            self.max / 1e3
+        )?;
+        write!(
+            f,
+            "outliers: {} ({} low severe, {} low mild, {} high mild, {} high severe)",
+            self.outliers.total(),
+            self.outliers.low_severe,
+            self.outliers.low_mild,
+            self.outliers.high_mild,
+            self.outliers.high_severe
+        )?;
+        if self.baseline > 0.0 {
+            write!(
+                f,
+                "\nbaseline: {:.3} us ({:.3} ns per op) of empty loop, subtracted from samples",
+                self.baseline / 1e3,
+                self.per_operation(self.baseline)
+            )?;
+        }
+        if self.near_baseline() {
+            write!(
+                f,
+                "\nwarning:  within {}% of the baseline; the work may have been optimized away",
+                NEAR_BASELINE * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Passes `value` through unchanged, but keeps the optimizer from assuming anything about it, so
+/// the work that produced it cannot be removed. `std::hint::black_box` is not available on
+/// stable Rust, so this relies on a volatile read instead.
+#[inline(always)]
+pub fn black_box<T>(value: T) -> T {
+    unsafe {
+        let result = ptr::read_volatile(&value);
+        mem::forget(value);
+        result
+    }
+}
+
+/// Runs `sample` `config.warmup` times without recording, followed by `config.samples` timed
+/// runs. `sample` returns the duration of its own timed region, so any setup it performs is
+/// excluded from the measurement. Every sample times `operations` operations.
+pub fn measure<F: FnMut() -> Duration>(
+    config: &MeasureConfig,
+    operations: usize,
+    mut sample: F,
+) -> Summary {
+    for _ in 0..config.warmup {
+        sample();
+    }
+
+    let samples: Vec<f64> = (0..config.samples)
+        .map(|_| sample().as_nanos() as f64)
+        .collect();
+
+    Summary::new(&samples, operations, config)
+}
+
+/// Measures `sample` like `measure`, alternating every run with a run of `baseline`, which
+/// does the same work except for what is measured. The median of the baseline is subtracted
+/// from every sample.
+pub fn measure_against<B, F>(
+    config: &MeasureConfig,
+    operations: usize,
+    mut baseline: B,
+    mut sample: F,
+) -> Summary
+where
+    B: FnMut() -> Duration,
+    F: FnMut() -> Duration,
+{
+    for _ in 0..config.warmup {
+        baseline();
+        sample();
+    }
+
+    let (mut baselines, mut samples) = (Vec::new(), Vec::new());
+    for _ in 0..config.samples {
+        baselines.push(baseline().as_nanos() as f64);
+        samples.push(sample().as_nanos() as f64);
+    }
+    baselines.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = percentile(&baselines, 0.5);
+    for sample in &mut samples {
+        *sample -= median;
+    }
+
+    Summary {
+        baseline: median,
+        ..Summary::new(&samples, operations, config)
+    }
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Sample standard deviation, using Bessel's correction.
+fn std_dev(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    let variance =
+        samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64;
+    variance.sqrt()
+}
+
+/// Linearly interpolated percentile of already sorted samples, with `p` in `[0, 1]`.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Percentile bootstrap confidence interval of the mean.
+fn bootstrap_mean_ci<R: Rng>(
+    samples: &[f64],
+    resamples: usize,
+    confidence: f64,
+    rng: &mut R,
+) -> (f64, f64) {
+    if resamples == 0 {
+        let mean = mean(samples);
+        return (mean, mean);
+    }
+
+    let mut means: Vec<f64> = (0..resamples)
+        .map(|_| {
+            let sum: f64 = (0..samples.len())
+                .map(|_| samples[rng.gen_range(0, samples.len())])
+                .sum();
+            sum / samples.len() as f64
+        })
+        .collect();
+    means.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let alpha = (1.0 - confidence) / 2.0;
+    (percentile(&means, alpha), percentile(&means, 1.0 - alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bootstrap_mean_ci, percentile, std_dev, Outliers, Summary};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn percentiles_interpolate_between_samples() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 1.0), 4.0);
+        assert_eq!(percentile(&sorted, 0.5), 2.5);
+        assert_eq!(percentile(&sorted, 1.0 / 3.0), 2.0);
+        assert_eq!(percentile(&sorted, 0.75), 3.25);
+        assert_eq!(percentile(&[7.0], 0.0), 7.0);
+        assert_eq!(percentile(&[7.0], 0.5), 7.0);
+        assert_eq!(percentile(&[7.0], 1.0), 7.0);
+    }
+
+    #[test]
+    fn outliers_are_classified_by_tukeys_fences() {
+        // The quartiles are 10 and 20, so the mild fences are -5 and 35 and the severe fences
+        // are -20 and 50. A sample on a fence is not beyond it.
+        let mut sorted = vec![-20.1, -20.0, -5.1, -5.0];
+        sorted.extend_from_slice(&[10.0; 10]);
+        sorted.extend_from_slice(&[20.0; 10]);
+        sorted.extend_from_slice(&[35.0, 35.1, 50.0, 50.1]);
+        assert_eq!(percentile(&sorted, 0.25), 10.0);
+        assert_eq!(percentile(&sorted, 0.75), 20.0);
+
+        let outliers = Outliers::classify(&sorted);
+        assert_eq!(
+            outliers,
+            Outliers {
+                low_severe: 1,
+                low_mild: 2,
+                high_mild: 2,
+                high_severe: 1,
+            }
+        );
+        assert_eq!(outliers.total(), 6);
+    }
+
+    #[test]
+    fn std_dev_uses_bessels_correction() {
+        let samples = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(std_dev(&samples), (32.0f64 / 7.0).sqrt());
+        assert_eq!(std_dev(&[3.0]), 0.0);
+    }
+
+    #[test]
+    fn bootstrap_without_resamples_is_the_mean() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let samples = [1.0, 2.0, 6.0];
+        assert_eq!(bootstrap_mean_ci(&samples, 0, 0.95, &mut rng), (3.0, 3.0));
+    }
+
+    #[test]
+    fn bootstrap_of_a_constant_is_the_constant() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let samples = [3.0; 10];
+        assert_eq!(
+            bootstrap_mean_ci(&samples, 1000, 0.95, &mut rng),
+            (3.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn bootstrap_depends_only_on_the_seed() {
+        let samples: Vec<f64> = (0..30).map(|x| (x * x % 17) as f64).collect();
+        let ci =
+            |seed| bootstrap_mean_ci(&samples, 1000, 0.95, &mut ChaCha8Rng::seed_from_u64(seed));
+        assert_eq!(ci(1), ci(1));
+        let (low, high) = ci(1);
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        assert!(
+            low < mean && mean < high,
+            "{} is outside ({}, {})",
+            mean,
+            low,
+            high
+        );
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let summary = Summary {
+            samples: 1,
+            operations: 2,
+            failures: 3,
+            mean: 4.5,
+            median: 5.25,
+            std_dev: 0.1,
+            min: -7.0,
+            max: 8e20,
+            ci: (9.125, 10.0625),
+            confidence: 0.95,
+            outliers: Outliers {
+                low_severe: 11,
+                low_mild: 12,
+                high_mild: 13,
+                high_severe: 14,
+            },
+            baseline: 1.0 / 3.0,
+        };
+
+        let parsed = Summary::from_record(&summary.to_record()).unwrap();
+        assert_eq!(parsed.samples, summary.samples);
+        assert_eq!(parsed.operations, summary.operations);
+        assert_eq!(parsed.failures, summary.failures);
+        assert_eq!(parsed.mean, summary.mean);
+        assert_eq!(parsed.median, summary.median);
+        assert_eq!(parsed.std_dev, summary.std_dev);
+        assert_eq!(parsed.min, summary.min);
+        assert_eq!(parsed.max, summary.max);
+        assert_eq!(parsed.ci, summary.ci);
+        assert_eq!(parsed.confidence, summary.confidence);
+        assert_eq!(parsed.outliers, summary.outliers);
+        assert_eq!(parsed.baseline, summary.baseline);
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert!(Summary::from_record("").is_err());
+        assert!(Summary::from_record("1 2 3 4 5 6 7 8 9 10 11 12 13 14 15").is_err());
+        assert!(Summary::from_record("1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17").is_err());
+        assert!(Summary::from_record("1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 x").is_err());
+        assert!(Summary::from_record("1 2 -3 4 5 6 7 8 9 10 11 12 13 14 15 16").is_err());
+        assert!(Summary::from_record("1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16").is_ok());
+    }
+}
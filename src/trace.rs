@@ -0,0 +1,406 @@
+//! A compact binary format for allocation traces.
+//!
+//! A trace starts with `MAGIC` and a version byte, followed by a sequence of events. Every event
+//! starts with a tag byte: its low bits hold the kind of event and `HAS_THREAD` marks whether a
+//! thread id follows the object id. All integers are LEB128-encoded, except for alignments,
+//! which are stored as a single byte holding their base-2 logarithm:
+//!
+//! ```text
+//! alloc:   tag id [thread] size log2(align)
+//! dealloc: tag id [thread]
+//! realloc: tag id [thread] new_size [new_id]
+//! ```
+//!
+//! Object ids only need to be unique among live objects, so e.g. addresses can be used. When a
+//! reallocation moves an object, `HAS_NEW_ID` marks that it is known by `new_id` afterwards.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"AWGTRACE";
+const VERSION: u8 = 1;
+
+const ALLOC: u8 = 0;
+const DEALLOC: u8 = 1;
+const REALLOC: u8 = 2;
+const KIND_MASK: u8 = 0x03;
+const HAS_NEW_ID: u8 = 0x40;
+const HAS_THREAD: u8 = 0x80;
+
+/// The maximum length of an encoded event: a tag, four 64-bit integers and an alignment.
+pub const MAX_EVENT_LEN: usize = 1 + 4 * 10 + 1;
+
+/// An operation on an object of a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Alloc {
+        size: usize,
+        align: usize,
+    },
+    Dealloc,
+    /// Resizes the object to `size` bytes, keeping its alignment. If `new_id` is given, the
+    /// object is referred to by it afterwards.
+    Realloc {
+        size: usize,
+        new_id: Option<u64>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub id: u64,
+    pub thread: Option<u64>,
+    pub operation: Operation,
+}
+
+impl Event {
+    /// Encodes the event into `buf` without allocating, and returns the number of bytes used.
+    pub fn encode(&self, buf: &mut [u8; MAX_EVENT_LEN]) -> usize {
+        let kind = match self.operation {
+            Operation::Alloc { .. } => ALLOC,
+            Operation::Dealloc => DEALLOC,
+            Operation::Realloc { new_id: None, .. } => REALLOC,
+            Operation::Realloc {
+                new_id: Some(_), ..
+            } => REALLOC | HAS_NEW_ID,
+        };
+        buf[0] = if self.thread.is_some() {
+            kind | HAS_THREAD
+        } else {
+            kind
+        };
+
+        let mut len = 1;
+        len += encode_varint(&mut buf[len..], self.id);
+        if let Some(thread) = self.thread {
+            len += encode_varint(&mut buf[len..], thread);
+        }
+        match self.operation {
+            Operation::Alloc { size, align } => {
+                len += encode_varint(&mut buf[len..], size as u64);
+                buf[len] = align.trailing_zeros() as u8;
+                len += 1;
+            }
+            Operation::Dealloc => {}
+            Operation::Realloc { size, new_id } => {
+                len += encode_varint(&mut buf[len..], size as u64);
+                if let Some(new_id) = new_id {
+                    len += encode_varint(&mut buf[len..], new_id);
+                }
+            }
+        }
+        len
+    }
+}
+
+fn encode_varint(buf: &mut [u8], mut value: u64) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+/// Writes the header of a trace, which has to precede its events.
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])
+}
+
+pub struct TraceWriter<W: Write> {
+    writer: W,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TraceWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        write_header(&mut writer)?;
+        Ok(TraceWriter { writer })
+    }
+
+    pub fn write(&mut self, event: &Event) -> io::Result<()> {
+        let mut buf = [0; MAX_EVENT_LEN];
+        let len = event.encode(&mut buf);
+        self.writer.write_all(&buf[..len])
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the events of a trace, in order.
+pub struct TraceReader<R: Read> {
+    reader: R,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TraceReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid_data("not an allocation trace"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid_data("unsupported trace version"));
+        }
+        Ok(TraceReader { reader })
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn read_varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self
+                .read_byte()?
+                .ok_or_else(|| invalid_data("truncated event"))?;
+            // The tenth byte only holds the highest bit.
+            if shift == 63 && byte & 0x7e != 0 {
+                return Err(invalid_data("integer overflow"));
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("integer overflow"))
+    }
+
+    fn read_event(&mut self, tag: u8) -> io::Result<Event> {
+        if tag & !(KIND_MASK | HAS_NEW_ID | HAS_THREAD) != 0 {
+            return Err(invalid_data("unknown event"));
+        }
+        let id = self.read_varint()?;
+        let thread = if tag & HAS_THREAD != 0 {
+            Some(self.read_varint()?)
+        } else {
+            None
+        };
+        let operation = match tag & KIND_MASK {
+            ALLOC => {
+                let size = self.read_varint()? as usize;
+                let log2 = self
+                    .read_byte()?
+                    .ok_or_else(|| invalid_data("truncated event"))?;
+                if log2 >= usize::MAX.count_ones() as u8 {
+                    return Err(invalid_data("invalid alignment"));
+                }
+                Operation::Alloc {
+                    size,
+                    align: 1 << log2,
+                }
+            }
+            DEALLOC => Operation::Dealloc,
+            REALLOC => Operation::Realloc {
+                size: self.read_varint()? as usize,
+                new_id: if tag & HAS_NEW_ID != 0 {
+                    Some(self.read_varint()?)
+                } else {
+                    None
+                },
+            },
+            _ => return Err(invalid_data("unknown event")),
+        };
+        Ok(Event {
+            id,
+            thread,
+            operation,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_byte() {
+            Ok(Some(tag)) => Some(self.read_event(tag)),
+            Ok(None) => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_varint, Event, Operation, TraceReader, TraceWriter, MAGIC, VERSION};
+    use std::io;
+
+    /// Values on both sides of every length of an encoded integer.
+    fn boundaries() -> Vec<u64> {
+        let mut values = vec![0, 1, u64::MAX - 1, u64::MAX];
+        for bits in (7..64).step_by(7) {
+            values.extend_from_slice(&[(1 << bits) - 1, 1 << bits]);
+        }
+        values
+    }
+
+    /// Events of every kind, with and without their optional fields.
+    fn events() -> Vec<Event> {
+        let mut events = Vec::new();
+        for (index, &value) in boundaries().iter().enumerate() {
+            let thread = if index % 2 == 0 { None } else { Some(value) };
+            let size = value as usize;
+            events.push(Event {
+                id: value,
+                thread,
+                operation: Operation::Alloc {
+                    size,
+                    align: 1 << (index % 64),
+                },
+            });
+            events.push(Event {
+                id: value,
+                thread,
+                operation: Operation::Realloc { size, new_id: None },
+            });
+            events.push(Event {
+                id: value,
+                thread: Some(index as u64),
+                operation: Operation::Realloc {
+                    size,
+                    new_id: Some(value),
+                },
+            });
+            events.push(Event {
+                id: value,
+                thread,
+                operation: Operation::Dealloc,
+            });
+        }
+        events
+    }
+
+    fn write(events: &[Event]) -> Vec<u8> {
+        let mut writer = TraceWriter::new(Vec::new()).unwrap();
+        for event in events {
+            writer.write(event).unwrap();
+        }
+        writer.writer
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<Vec<Event>> {
+        TraceReader::new(bytes)?.collect()
+    }
+
+    #[test]
+    fn integers_use_seven_bits_per_byte() {
+        let mut buf = [0; 10];
+        for &value in boundaries().iter() {
+            let bits = 64 - value.leading_zeros() as usize;
+            let expected = if bits == 0 { 1 } else { (bits - 1) / 7 + 1 };
+            assert_eq!(encode_varint(&mut buf, value), expected, "{}", value);
+        }
+        assert_eq!(encode_varint(&mut buf, u64::MAX), 10);
+    }
+
+    #[test]
+    fn events_survive_a_round_trip() {
+        let events = events();
+        assert_eq!(read(&write(&events)).unwrap(), events);
+        assert_eq!(read(&write(&[])).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn truncated_traces_are_rejected() {
+        let events = events();
+        let bytes = write(&events);
+        for len in 0..bytes.len() {
+            match read(&bytes[..len]) {
+                // Cutting between two events leaves a shorter, valid trace.
+                Ok(prefix) => assert_eq!(prefix[..], events[..prefix.len()]),
+                // A truncated header cannot be read at all.
+                Err(error) if len <= MAGIC.len() => {
+                    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof)
+                }
+                Err(error) => assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", len),
+            }
+        }
+        assert!(read(&bytes[..MAGIC.len()]).is_err());
+        let alloc = write(&events[..1]);
+        assert!(read(&alloc[..alloc.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn corrupt_traces_are_rejected() {
+        let header = || {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bytes
+        };
+        let corrupt = |event: &[u8]| {
+            let mut bytes = header();
+            bytes.extend_from_slice(event);
+            read(&bytes)
+        };
+
+        let mut wrong_magic = header();
+        wrong_magic[0] = b'X';
+        assert!(read(&wrong_magic).is_err());
+        let mut wrong_version = header();
+        wrong_version[8] = VERSION + 1;
+        assert!(read(&wrong_version).is_err());
+
+        // An unknown kind, and unknown flags.
+        assert!(corrupt(&[0x03, 1]).is_err());
+        assert!(corrupt(&[0x21, 1]).is_err());
+        // Integers of more than ten bytes, or with more than 64 bits.
+        assert!(
+            corrupt(&[0x01, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0])
+                .is_err()
+        );
+        assert!(
+            corrupt(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]).is_err()
+        );
+        assert!(
+            corrupt(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_ok()
+        );
+        // An alignment of 2^64.
+        assert!(corrupt(&[0x00, 1, 8, 64]).is_err());
+    }
+
+    #[test]
+    fn random_bytes_do_not_panic() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bytes.extend((0..rng.gen_range(0, 64)).map(|_| rng.gen::<u8>()));
+            let _ = read(&bytes);
+        }
+    }
+}
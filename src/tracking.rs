@@ -0,0 +1,312 @@
+//! An allocator wrapper that counts the calls that reach an allocator.
+
+use crate::AllocRefV2;
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::ptr::NonNull;
+
+/// The number of power-of-two buckets of the size and alignment histograms.
+const BUCKETS: usize = mem::size_of::<usize>() * 8 + 1;
+
+/// What `Tracking` observed so far.
+#[derive(Clone)]
+pub struct TrackingStats {
+    pub allocs: u64,
+    pub allocs_zeroed: u64,
+    pub deallocs: u64,
+    pub grows: u64,
+    pub shrinks: u64,
+    /// The number of calls that returned an error.
+    pub failures: u64,
+    /// The number of bytes requested by allocations and reallocations in total.
+    pub requested_bytes: u64,
+    /// The number of bytes in live allocations, and its maximum so far.
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    /// The number of live allocations, and its maximum so far.
+    pub live_allocs: usize,
+    pub peak_allocs: usize,
+    /// `sizes[i]` counts the requested sizes that round up to `2^i`.
+    pub sizes: [u64; BUCKETS],
+    /// `aligns[i]` counts the requested alignments of `2^i`.
+    pub aligns: [u64; BUCKETS],
+}
+
+/// The histogram bucket of `size`, which is the exponent of the power of two it rounds up to.
+fn size_bucket(size: usize) -> usize {
+    (BUCKETS - 1) - size.saturating_sub(1).leading_zeros() as usize
+}
+
+impl TrackingStats {
+    fn new() -> Self {
+        TrackingStats {
+            allocs: 0,
+            allocs_zeroed: 0,
+            deallocs: 0,
+            grows: 0,
+            shrinks: 0,
+            failures: 0,
+            requested_bytes: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+            live_allocs: 0,
+            peak_allocs: 0,
+            sizes: [0; BUCKETS],
+            aligns: [0; BUCKETS],
+        }
+    }
+
+    fn request(&mut self, layout: Layout) {
+        self.requested_bytes += layout.size() as u64;
+        self.sizes[size_bucket(layout.size())] += 1;
+        self.aligns[layout.align().trailing_zeros() as usize] += 1;
+    }
+
+    fn add_live(&mut self, layout: Layout) {
+        self.live_bytes += layout.size();
+        self.live_allocs += 1;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        self.peak_allocs = self.peak_allocs.max(self.live_allocs);
+    }
+
+    fn remove_live(&mut self, layout: Layout) {
+        self.live_bytes -= layout.size();
+        self.live_allocs -= 1;
+    }
+}
+
+/// Writes the non-empty buckets of a power-of-two histogram.
+fn write_buckets(
+    f: &mut fmt::Formatter,
+    label: &str,
+    buckets: &[u64],
+    prefix: &str,
+) -> fmt::Result {
+    write!(f, "{:<10}", format!("{}:", label))?;
+    let mut empty = true;
+    for (exponent, count) in buckets.iter().enumerate().filter(|(_, count)| **count > 0) {
+        if !empty {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}{}: {}", prefix, 1u128 << exponent, count)?;
+        empty = false;
+    }
+    if empty {
+        write!(f, "none")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for TrackingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "calls:    {} alloc, {} alloc_zeroed, {} dealloc, {} grow, {} shrink; {} failed",
+            self.allocs, self.allocs_zeroed, self.deallocs, self.grows, self.shrinks, self.failures
+        )?;
+        writeln!(
+            f,
+            "bytes:    {} requested in total, {} in use in {} allocations",
+            self.requested_bytes, self.live_bytes, self.live_allocs
+        )?;
+        writeln!(
+            f,
+            "peak:     {} bytes in {} allocations",
+            self.peak_bytes, self.peak_allocs
+        )?;
+        write_buckets(f, "sizes", &self.sizes, "<=")?;
+        writeln!(f)?;
+        write_buckets(f, "aligns", &self.aligns, "")
+    }
+}
+
+/// Counts the calls to `A`, the bytes that they request and the memory that is in use, and
+/// delegates them to `A`. `&Tracking<A>` is an allocator itself, so it can also back a `Bump`,
+/// to see the chunks that it requests.
+pub struct Tracking<A> {
+    inner: A,
+    stats: RefCell<TrackingStats>,
+}
+
+impl<A> Tracking<A> {
+    pub fn new(inner: A) -> Self {
+        Tracking {
+            inner,
+            stats: RefCell::new(TrackingStats::new()),
+        }
+    }
+
+    pub fn stats(&self) -> TrackingStats {
+        self.stats.borrow().clone()
+    }
+
+    fn record_alloc(
+        &self,
+        layout: Layout,
+        zeroed: bool,
+        result: Result<NonNull<u8>, AllocErr>,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let mut stats = self.stats.borrow_mut();
+        if zeroed {
+            stats.allocs_zeroed += 1;
+        } else {
+            stats.allocs += 1;
+        }
+        stats.request(layout);
+        match result {
+            Ok(_) => stats.add_live(layout),
+            Err(_) => stats.failures += 1,
+        }
+        result
+    }
+
+    fn record_dealloc(&self, layout: Layout) {
+        let mut stats = self.stats.borrow_mut();
+        stats.deallocs += 1;
+        stats.remove_live(layout);
+    }
+
+    fn record_realloc(
+        &self,
+        layout: Layout,
+        new_layout: Layout,
+        result: Result<NonNull<u8>, AllocErr>,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let mut stats = self.stats.borrow_mut();
+        if new_layout.size() >= layout.size() {
+            stats.grows += 1;
+        } else {
+            stats.shrinks += 1;
+        }
+        stats.request(new_layout);
+        match result {
+            Ok(_) => {
+                stats.remove_live(layout);
+                stats.add_live(new_layout);
+            }
+            Err(_) => stats.failures += 1,
+        }
+        result
+    }
+}
+
+impl<A: AllocRef> AllocRef for &Tracking<A> {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::alloc(self.inner, layout);
+        self.record_alloc(layout.into(), false, result)
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::alloc_zeroed(self.inner, layout);
+        self.record_alloc(layout.into(), true, result)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.record_dealloc(layout.into());
+        AllocRef::dealloc(self.inner, ptr, layout)
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::realloc(self.inner, ptr, layout, new_layout);
+        self.record_realloc(layout.into(), new_layout.into(), result)
+    }
+}
+
+impl<A: AllocRefV2> AllocRefV2 for &Tracking<A> {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_non_zst(layout);
+        self.record_alloc(layout.into(), false, result)
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_zeroed_non_zst(layout);
+        self.record_alloc(layout.into(), true, result)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.record_dealloc(layout.into());
+        self.inner.dealloc_non_zst(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.grow_non_zst(ptr, layout, new_layout);
+        self.record_realloc(layout.into(), new_layout.into(), result)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.shrink_non_zst(ptr, layout, new_layout);
+        self.record_realloc(layout.into(), new_layout.into(), result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{size_bucket, Tracking};
+    use crate::conformance::layout;
+    use crate::AllocRefV2;
+    use std::alloc::System;
+
+    #[test]
+    fn sizes_round_up_to_a_power_of_two() {
+        assert_eq!(size_bucket(1), 0);
+        assert_eq!(size_bucket(2), 1);
+        assert_eq!(size_bucket(3), 2);
+        assert_eq!(size_bucket(1024), 10);
+        assert_eq!(size_bucket(1025), 11);
+        assert_eq!(size_bucket(usize::MAX), super::BUCKETS - 1);
+    }
+
+    #[test]
+    fn calls_and_live_memory_are_counted() {
+        let tracking = Tracking::new(System);
+        let a = tracking.alloc(layout(100, 8)).unwrap();
+        let b = tracking.alloc_zeroed(layout(28, 4)).unwrap();
+        // Zero-sized allocations never reach the inner allocator.
+        let zst = tracking.alloc(layout(0, 1)).unwrap();
+        unsafe {
+            let a = tracking.grow(a, layout(100, 8), 200).unwrap();
+            tracking.dealloc(b, layout(28, 4));
+            let a = tracking.shrink(a, layout(200, 8), 50).unwrap();
+            tracking.dealloc(a, layout(50, 8));
+            tracking.dealloc(zst, layout(0, 1));
+        }
+
+        let stats = tracking.stats();
+        assert_eq!(
+            (stats.allocs, stats.allocs_zeroed, stats.deallocs),
+            (1, 1, 2)
+        );
+        assert_eq!((stats.grows, stats.shrinks, stats.failures), (1, 1, 0));
+        assert_eq!(stats.requested_bytes, 100 + 28 + 200 + 50);
+        assert_eq!((stats.live_bytes, stats.live_allocs), (0, 0));
+        assert_eq!((stats.peak_bytes, stats.peak_allocs), (228, 2));
+        assert_eq!(stats.sizes[7], 1);
+        assert_eq!(stats.sizes[5], 1);
+        assert_eq!(stats.sizes[6], 1);
+        assert_eq!(stats.sizes[8], 1);
+        assert_eq!((stats.aligns[2], stats.aligns[3]), (1, 3));
+    }
+}
@@ -0,0 +1,456 @@
+//! An allocator wrapper that checks every pointer that an allocator returns.
+
+use crate::AllocRefV2;
+use alloc_wg::alloc::{AllocErr, AllocRef, NonZeroLayout};
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ptr::{self, NonNull};
+
+/// The number of violations that are described in full; later ones are only counted.
+const MAX_REPORTED: usize = 10;
+
+/// What `Verifying` found so far.
+#[derive(Clone, Default)]
+pub struct Verification {
+    /// The number of pointers that were checked.
+    pub pointers: u64,
+    /// The number of calls that failed, and returned no pointer to check.
+    pub failures: u64,
+    /// The number of checks that failed.
+    pub violations: u64,
+    /// Descriptions of the first violations.
+    pub reported: Vec<String>,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.violations == 0
+    }
+
+    fn violation(&mut self, description: String) {
+        self.violations += 1;
+        if self.reported.len() < MAX_REPORTED {
+            self.reported.push(description);
+        }
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "verify:   {} pointers checked, ", self.pointers)?;
+        if self.failures > 0 {
+            write!(f, "{} calls failed, ", self.failures)?;
+        }
+        if self.passed() {
+            return write!(f, "no violations");
+        }
+        write!(f, "{} violations", self.violations)?;
+        for description in &self.reported {
+            write!(f, "\n          {}", description)?;
+        }
+        if self.violations > self.reported.len() as u64 {
+            write!(
+                f,
+                "\n          ... and {} more",
+                self.violations - self.reported.len() as u64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct State {
+    verification: Verification,
+    /// The start and end addresses of the live non-zero-sized allocations.
+    live: BTreeMap<usize, usize>,
+}
+
+/// Checks the pointers that `A` returns, and delegates all calls to `A`:
+///
+/// - every pointer satisfies the requested alignment,
+/// - non-zero-sized allocations do not overlap any other live allocation,
+/// - their memory can be written and read back across the full size, and zeroed allocations
+///   read as zeros first,
+/// - zero-sized allocations get the dangling pointer for their alignment.
+///
+/// The memory that is handed to the caller is left intact: only newly allocated bytes are
+/// written, and zeroed bytes are zeroed again afterwards.
+pub struct Verifying<A> {
+    inner: A,
+    state: RefCell<State>,
+}
+
+impl<A> Verifying<A> {
+    pub fn new(inner: A) -> Self {
+        Verifying {
+            inner,
+            state: RefCell::new(State {
+                verification: Verification::default(),
+                live: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn verification(&self) -> Verification {
+        self.state.borrow().verification.clone()
+    }
+
+    /// Checks a pointer to a zero-sized allocation.
+    fn check_zst(
+        &self,
+        layout: Layout,
+        result: Result<NonNull<u8>, AllocErr>,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let mut state = self.state.borrow_mut();
+        if let Ok(ptr) = result {
+            state.verification.pointers += 1;
+            if ptr.as_ptr() as usize != layout.align() {
+                state.verification.violation(format!(
+                    "zero-sized {:?} at {:p}, expected the dangling pointer {:#x}",
+                    layout,
+                    ptr,
+                    layout.align()
+                ));
+            }
+        } else {
+            state.verification.failures += 1;
+        }
+        result
+    }
+
+    /// Checks a pointer to a non-zero-sized allocation, of which the bytes from `fresh` on were
+    /// newly allocated. `zeroed` bytes are expected to read as zeros.
+    fn check(
+        &self,
+        layout: Layout,
+        fresh: usize,
+        zeroed: bool,
+        result: Result<NonNull<u8>, AllocErr>,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let mut state = self.state.borrow_mut();
+        let ptr = match result {
+            Ok(ptr) => ptr,
+            Err(_) => {
+                state.verification.failures += 1;
+                return result;
+            }
+        };
+        state.verification.pointers += 1;
+
+        let start = ptr.as_ptr() as usize;
+        if start & (layout.align() - 1) != 0 {
+            state
+                .verification
+                .violation(format!("{:?} at {:p} is misaligned", layout, ptr));
+        }
+
+        let end = start + layout.size();
+        let before = state.live.range(..end).next_back().map(|(s, e)| (*s, *e));
+        match before {
+            Some((other_start, other_end)) if other_end > start => {
+                state.verification.violation(format!(
+                    "{:?} at {:#x}..{:#x} overlaps the live allocation at {:#x}..{:#x}",
+                    layout, start, end, other_start, other_end
+                ));
+            }
+            _ => {}
+        }
+        state.live.insert(start, end);
+        drop(state);
+
+        let byte = |index: usize| unsafe { ptr.as_ptr().add(index) };
+        let fresh = fresh.min(layout.size())..layout.size();
+        if zeroed
+            && fresh
+                .clone()
+                .any(|index| unsafe { ptr::read_volatile(byte(index)) } != 0)
+        {
+            self.state
+                .borrow_mut()
+                .verification
+                .violation(format!("zeroed {:?} at {:p} is not zeroed", layout, ptr));
+        }
+        let readable = fresh.clone().all(|index| unsafe {
+            ptr::write_volatile(byte(index), pattern(index));
+            ptr::read_volatile(byte(index)) == pattern(index)
+        });
+        if !readable {
+            self.state.borrow_mut().verification.violation(format!(
+                "{:?} at {:p} does not read back what was written",
+                layout, ptr
+            ));
+        }
+        if zeroed {
+            unsafe { ptr::write_bytes(byte(fresh.start), 0, fresh.len()) };
+        }
+        result
+    }
+
+    fn release(&self, ptr: NonNull<u8>) {
+        self.state
+            .borrow_mut()
+            .live
+            .remove(&(ptr.as_ptr() as usize));
+    }
+
+    /// Releases the allocation at `ptr` if it was moved by a successful reallocation.
+    fn release_moved(&self, ptr: NonNull<u8>, result: Result<NonNull<u8>, AllocErr>) {
+        if result.is_ok() {
+            self.release(ptr);
+        }
+    }
+}
+
+/// The byte that the write and read-back check writes at `index`.
+fn pattern(index: usize) -> u8 {
+    (index as u8) ^ 0xa5
+}
+
+impl<A: AllocRef> AllocRef for &Verifying<A> {
+    fn alloc(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::alloc(self.inner, layout);
+        self.check(layout.into(), 0, false, result)
+    }
+
+    fn alloc_zeroed(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::alloc_zeroed(self.inner, layout);
+        self.check(layout.into(), 0, true, result)
+    }
+
+    unsafe fn dealloc(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.release(ptr);
+        AllocRef::dealloc(self.inner, ptr, layout)
+    }
+
+    unsafe fn realloc(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = AllocRef::realloc(self.inner, ptr, layout, new_layout);
+        self.release_moved(ptr, result);
+        self.check(
+            new_layout.into(),
+            Layout::from(layout).size(),
+            false,
+            result,
+        )
+    }
+}
+
+impl<A: AllocRefV2> AllocRefV2 for &Verifying<A> {
+    #[inline(always)]
+    fn alloc_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_non_zst(layout);
+        self.check(layout.into(), 0, false, result)
+    }
+
+    #[inline(always)]
+    fn alloc_zst(self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_zst(layout);
+        self.check_zst(layout, result)
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_zeroed_non_zst(layout);
+        self.check(layout.into(), 0, true, result)
+    }
+
+    #[inline(always)]
+    fn alloc_zeroed_zst(self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.alloc_zeroed_zst(layout);
+        self.check_zst(layout, result)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_non_zst(self, ptr: NonNull<u8>, layout: NonZeroLayout) {
+        self.release(ptr);
+        self.inner.dealloc_non_zst(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc_zst(self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.dealloc_zst(ptr, layout)
+    }
+
+    #[inline(always)]
+    unsafe fn grow_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.grow_non_zst(ptr, layout, new_layout);
+        self.release_moved(ptr, result);
+        self.check(
+            new_layout.into(),
+            Layout::from(layout).size(),
+            false,
+            result,
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn grow_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.grow_zst(ptr, layout, new_layout);
+        self.check(new_layout.into(), 0, false, result)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_non_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: NonZeroLayout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.shrink_non_zst(ptr, layout, new_layout);
+        self.release_moved(ptr, result);
+        let size = Layout::from(new_layout).size();
+        self.check(new_layout.into(), size, false, result)
+    }
+
+    #[inline(always)]
+    unsafe fn shrink_zst(
+        self,
+        ptr: NonNull<u8>,
+        layout: NonZeroLayout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocErr> {
+        let result = self.inner.shrink_zst(ptr, layout, new_layout);
+        self.release_moved(ptr, result);
+        self.check_zst(new_layout, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Verifying;
+    use crate::cli::Options;
+    use crate::conformance::layout;
+    use crate::scenario::{Allocator, Call, Choice, Mode, Scenario, Sizes};
+    use crate::{verify_scenario, AllocRefV2, Workload};
+    use alloc_wg::alloc::{AllocErr, NonZeroLayout};
+    use std::alloc::{Layout, System};
+    use std::ptr::NonNull;
+
+    /// Hands out the same misaligned pointer into a buffer for every allocation, and the wrong
+    /// pointer for zero-sized ones.
+    #[derive(Clone, Copy)]
+    struct Broken(NonNull<u8>);
+
+    impl AllocRefV2 for Broken {
+        fn alloc_non_zst(self, _layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+            Ok(unsafe { NonNull::new_unchecked(self.0.as_ptr().add(1)) })
+        }
+
+        fn alloc_zst(self, _layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+            Ok(NonNull::dangling())
+        }
+
+        fn alloc_zeroed_non_zst(self, layout: NonZeroLayout) -> Result<NonNull<u8>, AllocErr> {
+            self.alloc_non_zst(layout)
+        }
+
+        unsafe fn dealloc_non_zst(self, _ptr: NonNull<u8>, _layout: NonZeroLayout) {}
+
+        unsafe fn grow_non_zst(
+            self,
+            _ptr: NonNull<u8>,
+            _layout: NonZeroLayout,
+            _new_layout: NonZeroLayout,
+        ) -> Result<NonNull<u8>, AllocErr> {
+            Err(AllocErr)
+        }
+
+        unsafe fn shrink_non_zst(
+            self,
+            _ptr: NonNull<u8>,
+            _layout: NonZeroLayout,
+            _new_layout: NonZeroLayout,
+        ) -> Result<NonNull<u8>, AllocErr> {
+            Err(AllocErr)
+        }
+    }
+
+    #[test]
+    fn correct_pointers_pass() {
+        let verifying = Verifying::new(System);
+        let a = verifying.alloc(layout(100, 16)).unwrap();
+        let b = verifying.alloc_zeroed(layout(64, 8)).unwrap();
+        let zst = verifying.alloc(layout(0, 32)).unwrap();
+        unsafe {
+            assert_eq!(*b.as_ptr().add(63), 0, "zeroed memory stays zeroed");
+            let a = verifying.grow(a, layout(100, 16), 4000).unwrap();
+            let a = verifying.shrink(a, layout(4000, 16), 0).unwrap();
+            verifying.dealloc(a, layout(0, 16));
+            verifying.dealloc(b, layout(64, 8));
+            verifying.dealloc(zst, layout(0, 32));
+        }
+
+        let verification = verifying.verification();
+        assert_eq!(verification.pointers, 5);
+        assert!(verification.passed(), "{}", verification);
+    }
+
+    #[test]
+    fn broken_pointers_are_reported() {
+        let mut buffer = [0u64; 8];
+        let verifying = Verifying::new(Broken(NonNull::from(&mut buffer).cast()));
+        verifying.alloc(layout(8, 1)).unwrap();
+        // Misaligned, and overlapping the first allocation.
+        verifying.alloc(layout(8, 8)).unwrap();
+        // Not the dangling pointer for an alignment of 8.
+        verifying.alloc(layout(0, 8)).unwrap();
+        // The dangling pointer for an alignment of 1.
+        verifying.alloc(layout(0, 1)).unwrap();
+
+        let verification = verifying.verification();
+        assert_eq!(verification.pointers, 4);
+        assert_eq!(verification.violations, 3, "{}", verification);
+        assert!(verification.reported[0].contains("misaligned"));
+        assert!(verification.reported[1].contains("overlaps"));
+        assert!(verification.reported[2].contains("dangling"));
+    }
+
+    /// Every allocator returns valid pointers in every mode of the harness, and none of its calls
+    /// fail.
+    #[test]
+    fn every_allocator_returns_valid_pointers() {
+        let options = Options {
+            iters: 300,
+            seed: 5,
+            ..Options::default()
+        };
+        for &allocator in Allocator::ALL {
+            for &sizes in Sizes::ALL {
+                for &call in Call::ALL {
+                    for &mode in Mode::ALL {
+                        let scenario = Scenario {
+                            allocator,
+                            sizes,
+                            call,
+                            mode,
+                            ..options.scenario()
+                        };
+                        if scenario.validate().is_err() {
+                            continue;
+                        }
+                        let workload = Workload::new(&scenario);
+                        let verification = verify_scenario(&scenario, &workload);
+                        assert!(verification.passed(), "{}: {}", scenario, verification);
+                        assert_eq!(verification.failures, 0, "{}: {}", scenario, verification);
+                    }
+                }
+            }
+        }
+    }
+}